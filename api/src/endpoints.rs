@@ -4,11 +4,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     params::ToParams,
-    proto::{CommonUpdate, Message, WebhookInfo},
+    proto::{ChatMember, CommonUpdate, Message, User, WebhookInfo},
     request::{
         CopyMessageRequest, DeleteMessageRequest, DeleteWebhookRequest, ForwardMessageRequest,
-        GetUpdatesRequest, SendAnimationRequest, SendChatActionRequest, SendMessageRequest,
-        SendPhotoRequest, SetWebhookRequest,
+        GetChatMemberRequest, GetUpdatesRequest, SendAnimationRequest, SendChatActionRequest,
+        SendMessageRequest, SendPhotoRequest, SetMyCommandsRequest, SetWebhookRequest,
     },
     response::MessageIdResponse,
 };
@@ -128,3 +128,30 @@ impl Endpoint for GetWebhookInfo {
     const METHOD: Method = Method::POST;
     const PATH: &'static str = "getWebhookInfo";
 }
+
+pub struct SetMyCommands;
+
+impl Endpoint for SetMyCommands {
+    type Request = SetMyCommandsRequest;
+    type Response = bool;
+    const METHOD: Method = Method::POST;
+    const PATH: &'static str = "setMyCommands";
+}
+
+pub struct GetChatMember;
+
+impl Endpoint for GetChatMember {
+    type Request = GetChatMemberRequest;
+    type Response = ChatMember;
+    const METHOD: Method = Method::POST;
+    const PATH: &'static str = "getChatMember";
+}
+
+pub struct GetMe;
+
+impl Endpoint for GetMe {
+    type Request = Empty;
+    type Response = User;
+    const METHOD: Method = Method::GET;
+    const PATH: &'static str = "getMe";
+}
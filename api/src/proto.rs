@@ -81,13 +81,13 @@ pub enum UpdateType {
     ChatJoinRequest,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CommonUpdate {
     pub id: UpdateId,
     pub data: Update,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Update {
     MessageUpdate(Message),
     EditedMessageUpdate(Message),
@@ -169,7 +169,7 @@ impl<'de> Deserialize<'de> for CommonUpdate {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct InlineQuery {
     pub id: CompactString,
     pub from: User,
@@ -180,7 +180,7 @@ pub struct InlineQuery {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChosenInlineResult {
     pub result_id: i64,
     pub from: User,
@@ -189,7 +189,7 @@ pub struct ChosenInlineResult {
     pub query: Option<CompactString>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct CallbackQuery {
     pub id: CompactString,
     pub from: User,
@@ -200,7 +200,7 @@ pub struct CallbackQuery {
     pub game_short_name: Option<CompactString>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ShippingQuery {
     pub id: CompactString,
     pub from: User,
@@ -210,7 +210,7 @@ pub struct ShippingQuery {
 
 /// This object represents a shipping address.
 /// https://core.telegram.org/bots/api#shippingaddress
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ShippingAddress {
     pub country_code: CompactString,
     pub state: CompactString,
@@ -223,7 +223,7 @@ pub struct ShippingAddress {
 /// This object contains information about an incoming pre-checkout query.
 /// https://core.telegram.org/bots/api#precheckoutquery
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PreCheckoutQuery {
     pub id: CompactString,
     pub from: User,
@@ -237,7 +237,7 @@ pub struct PreCheckoutQuery {
 /// This object represents information about an order.
 /// https://core.telegram.org/bots/api#orderinfo
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct OrderInfo {
     pub name: Option<CompactString>,
     pub phone_number: Option<CompactString>,
@@ -247,7 +247,7 @@ pub struct OrderInfo {
 
 /// This object represents an answer of a user in a non-anonymous poll.
 /// https://core.telegram.org/bots/api#pollanswer
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PollAnswer {
     poll_id: CompactString,
     user: User,
@@ -256,7 +256,7 @@ pub struct PollAnswer {
 
 /// This object represents changes in the status of a [chat member](https://core.telegram.org/bots/api#chatmember).
 /// https://core.telegram.org/bots/api#chatmemberupdated
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberUpdated {
     pub chat: Chat,
     pub from: User,
@@ -276,7 +276,7 @@ pub struct ChatMemberUpdated {
 /// - [ChatMemberBanned](https://core.telegram.org/bots/api#chatmemberbanned)
 /// https://core.telegram.org/bots/api#chatmember
 #[allow(clippy::enum_variant_names)]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "status")]
 pub enum ChatMember {
     #[serde(rename = "creator")]
@@ -293,9 +293,29 @@ pub enum ChatMember {
     ChatMemberBanned(ChatMemberBanned),
 }
 
+impl ChatMember {
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            ChatMember::ChatMemberOwner(_) | ChatMember::ChatMemberAdministrator(_)
+        )
+    }
+}
+
+/// This object represents a bot command.
+/// https://core.telegram.org/bots/api#botcommand
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BotCommand {
+    /// Text of the command; 1-32 characters.
+    /// Can contain only lowercase English letters, digits and underscores.
+    pub command: CompactString,
+    /// Description of the command; 1-256 characters.
+    pub description: CompactString,
+}
+
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that owns the chat and has all administrator privileges.
 /// https://core.telegram.org/bots/api#chatmemberowner
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberOwner {
     pub user: User,
     pub is_anonymous: bool,
@@ -304,7 +324,7 @@ pub struct ChatMemberOwner {
 
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that has some additional privileges.
 /// https://core.telegram.org/bots/api#chatmemberadministrator
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberAdministrator {
     pub user: User,
     pub can_be_edited: bool,
@@ -325,14 +345,14 @@ pub struct ChatMemberAdministrator {
 
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that has no additional privileges or restrictions.
 /// https://core.telegram.org/bots/api#chatmembermember
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberMember {
     pub user: User,
 }
 
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that is under certain restrictions in the chat. Supergroups only.
 /// https://core.telegram.org/bots/api#chatmemberrestricted
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberRestricted {
     pub user: User,
     pub is_member: bool,
@@ -355,14 +375,14 @@ pub struct ChatMemberRestricted {
 
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that isn't currently a member of the chat, but may join it themselves.
 /// https://core.telegram.org/bots/api#chatmemberleft
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberLeft {
     pub user: User,
 }
 
 /// Represents a [chat member](https://core.telegram.org/bots/api#chatmember) that was banned in the chat and can't return to the chat or view chat messages.
 /// https://core.telegram.org/bots/api#chatmemberbanned
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatMemberBanned {
     pub user: User,
     pub until_date: Date,
@@ -411,7 +431,7 @@ pub struct ChatMemberBanned {
 /// Represents an invite link for a chat.
 /// https://core.telegram.org/bots/api#chatinvitelink
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatInviteLink {
     pub invite_link: CompactString,
     pub creator: User,
@@ -426,7 +446,7 @@ pub struct ChatInviteLink {
 
 /// Represents a join request sent to a chat.
 /// https://core.telegram.org/bots/api#chatjoinrequest
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct ChatJoinRequest {
     pub chat: Chat,
     pub from: User,
@@ -436,7 +456,7 @@ pub struct ChatJoinRequest {
     pub invite_link: Option<ChatInviteLink>,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum ChatId {
     #[serde(deserialize_with = "deserialize_number_from_string")]
@@ -479,7 +499,7 @@ impl From<&ChatId> for ChatIntId {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ParseMode {
     #[serde(rename = "HTML")]
     Html,
@@ -487,7 +507,7 @@ pub enum ParseMode {
     MarkdownV2,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum ReplyMarkup {
     InlineKeyboardMarkup(InlineKeyboardMarkup),
@@ -500,7 +520,7 @@ pub enum ReplyMarkup {
 /// with reply options (see [Introduction to bots](https://core.telegram.org/bots/features#keyboards) for details and examples).
 /// https://core.telegram.org/bots/api#replykeyboardmarkup
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ReplyKeyboardMarkup {
     pub keyboard: Vec<Vec<KeyboardButton>>,
     pub is_persistent: Option<bool>,
@@ -516,7 +536,7 @@ pub struct ReplyKeyboardMarkup {
 /// `request_location`, and `request_poll` are mutually exclusive.
 /// https://core.telegram.org/bots/api#keyboardbutton
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct KeyboardButton {
     pub text: CompactString,
     pub request_user: Option<KeyboardButtonRequestUser>,
@@ -531,7 +551,7 @@ pub struct KeyboardButton {
 /// The identifier of the selected user will be shared with the bot when the corresponding button is pressed.
 /// https://core.telegram.org/bots/api#keyboardbuttonrequestuser
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct KeyboardButtonRequestUser {
     pub request_id: i32,
     pub user_is_bot: Option<bool>,
@@ -542,7 +562,7 @@ pub struct KeyboardButtonRequestUser {
 /// The identifier of the selected chat will be shared with the bot when the corresponding button is pressed.
 /// https://core.telegram.org/bots/api#keyboardbuttonrequestchat
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct KeyboardButtonRequestChat {
     pub request_id: i32,
     pub chat_is_channel: Option<bool>,
@@ -557,7 +577,7 @@ pub struct KeyboardButtonRequestChat {
 /// Represents the rights of an administrator in a chat.
 /// https://core.telegram.org/bots/api#chatadministratorrights
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatAdministratorRights {
     pub is_anonymous: bool,
     pub can_manage_chat: bool,
@@ -576,13 +596,13 @@ pub struct ChatAdministratorRights {
 /// This object represents type of a poll, which is allowed to be created and sent when the corresponding button is pressed.
 /// https://core.telegram.org/bots/api#keyboardbuttonpolltype
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct KeyboardButtonPollType {
     #[serde(default, rename = "type")]
     poll_type: Option<PollType>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum PollType {
     Quiz,
@@ -591,7 +611,7 @@ pub enum PollType {
 
 /// Describes a [Web App](https://core.telegram.org/bots/webapps).
 /// https://core.telegram.org/bots/webapps
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct WebAppInfo {
     pub url: CompactString,
 }
@@ -603,7 +623,7 @@ pub struct WebAppInfo {
 /// the user presses a button (see [ReplyKeyboardMarkup](https://core.telegram.org/bots/api#replykeyboardmarkup)).
 /// https://core.telegram.org/bots/api#replykeyboardremove
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ReplyKeyboardRemove {
     pub remove_keyboard: bool,
     pub selective: Option<bool>,
@@ -616,7 +636,7 @@ pub struct ReplyKeyboardRemove {
 /// without having to sacrifice [privacy mode](https://core.telegram.org/bots/features#privacy-mode).
 /// https://core.telegram.org/bots/api#forcereply
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ForceReply {
     pub force_reply: bool,
     pub input_field_placeholder: Option<CompactString>,
@@ -661,7 +681,7 @@ impl User {
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ChatType {
     Sender,
@@ -672,7 +692,7 @@ pub enum ChatType {
     Channel,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Chat {
     pub id: ChatIntId,
     #[serde(default, rename = "type")]
@@ -774,7 +794,7 @@ impl Default for Chat {
 
 /// Represents a location to which a chat is connected.
 /// https://core.telegram.org/bots/api#chatlocation
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatLocation {
     pub location: Location,
     pub address: CompactString,
@@ -783,7 +803,7 @@ pub struct ChatLocation {
 /// Describes actions that a non-administrator user is allowed to take in a chat.
 /// https://core.telegram.org/bots/api#chatpermissions
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatPermissions {
     pub can_send_messages: Option<bool>,
     pub can_send_audios: Option<bool>,
@@ -803,7 +823,7 @@ pub struct ChatPermissions {
 
 /// This object represents a chat photo.
 /// https://core.telegram.org/bots/api#chatphoto
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatPhoto {
     pub small_file_id: CompactString,
     pub small_file_unique_id: CompactString,
@@ -860,7 +880,7 @@ pub enum MessageEntityType {
 /// This object represents an animation file (GIF or H.264/MPEG-4 AVC video without sound).
 /// https://core.telegram.org/bots/api#animation
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Animation {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -876,7 +896,7 @@ pub struct Animation {
 /// This object represents an audio file to be treated as music by the Telegram clients.
 /// https://core.telegram.org/bots/api#audio
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Audio {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -892,7 +912,7 @@ pub struct Audio {
 /// This object represents a general file (as opposed to photos, voice messages and audio files).
 /// https://core.telegram.org/bots/api#document
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Document {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -907,7 +927,7 @@ pub struct Document {
 /// [sticker](https://core.telegram.org/bots/api#sticker) thumbnail.
 /// https://core.telegram.org/bots/api#photosize
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PhotoSize {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -918,7 +938,7 @@ pub struct PhotoSize {
 
 /// This object represents a sticker.
 /// https://core.telegram.org/bots/api#sticker
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Sticker {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -940,7 +960,7 @@ pub struct Sticker {
 
 /// This object describes the position on faces where a mask should be placed by default.
 /// https://core.telegram.org/bots/api#maskposition
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct MaskPosition {
     pub point: CompactString,
     pub x_shift: f32,
@@ -955,7 +975,7 @@ pub struct MaskPosition {
 /// by calling [getFile](https://core.telegram.org/bots/api#getfile).
 /// https://core.telegram.org/bots/api#file
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct File {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -966,7 +986,7 @@ pub struct File {
 /// Type of the sticker, currently one of “regular”, “mask”, “custom_emoji”.
 /// The type of the sticker is independent from its format,
 /// which is determined by the fields `is_animated` and `is_video`.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum StickerType {
     Regular,
@@ -977,7 +997,7 @@ pub enum StickerType {
 /// This object represents a video file.
 /// https://core.telegram.org/bots/api#video
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Video {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -994,7 +1014,7 @@ pub struct Video {
 /// (available in Telegram apps as of [v.4.0](https://telegram.org/blog/video-messages-and-telescope)).
 /// https://core.telegram.org/bots/api#videonote
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VideoNote {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -1007,7 +1027,7 @@ pub struct VideoNote {
 /// This object represents a voice note.
 /// https://core.telegram.org/bots/api#voice
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Voice {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -1018,7 +1038,7 @@ pub struct Voice {
 
 /// This object represents an animated emoji that displays a random value.
 /// https://core.telegram.org/bots/api#dice
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Dice {
     pub emoji: CompactString,
     pub value: u8,
@@ -1027,7 +1047,7 @@ pub struct Dice {
 /// This object represents a game. Use BotFather to create and edit games, their short names will act as unique identifiers.
 /// https://core.telegram.org/bots/api#game
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Game {
     pub title: CompactString,
     pub description: CompactString,
@@ -1040,7 +1060,7 @@ pub struct Game {
 /// This object contains information about a poll.
 /// https://core.telegram.org/bots/api#poll
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Poll {
     pub id: CompactString,
     pub question: CompactString,
@@ -1060,7 +1080,7 @@ pub struct Poll {
 
 /// This object contains information about one answer option in a poll.
 /// https://core.telegram.org/bots/api#polloption
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PollOption {
     text: CompactString,
     voter_count: i32,
@@ -1069,7 +1089,7 @@ pub struct PollOption {
 /// This object represents a venue.
 /// https://core.telegram.org/bots/api#venue
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Venue {
     pub location: Location,
     pub title: CompactString,
@@ -1082,7 +1102,7 @@ pub struct Venue {
 
 /// This object represents a service message about a change in auto-delete timer settings.
 /// https://core.telegram.org/bots/api#messageautodeletetimerchanged
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MessageAutoDeleteTimerChanged {
     pub message_auto_delete_time: i32,
 }
@@ -1090,7 +1110,7 @@ pub struct MessageAutoDeleteTimerChanged {
 /// This object represents a point on the map.
 /// https://core.telegram.org/bots/api#location
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Location {
     pub longitude: f32,
     pub latitude: f32,
@@ -1102,7 +1122,7 @@ pub struct Location {
 
 /// This object contains basic information about an invoice.
 /// https://core.telegram.org/bots/api#invoice
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Invoice {
     pub title: CompactString,
     pub description: CompactString,
@@ -1114,7 +1134,7 @@ pub struct Invoice {
 /// This object contains basic information about a successful payment.
 /// https://core.telegram.org/bots/api#successfulpayment
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SuccessfulPayment {
     pub currency: CompactString,
     pub total_amount: i32,
@@ -1128,7 +1148,7 @@ pub struct SuccessfulPayment {
 /// This object contains information about the user whose identifier was shared with the bot
 /// using a [KeyboardButtonRequestUser](https://core.telegram.org/bots/api#keyboardbuttonrequestuser) button.
 /// https://core.telegram.org/bots/api#usershared
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct UserShared {
     pub request_id: i32,
     pub user_id: UserId,
@@ -1137,7 +1157,7 @@ pub struct UserShared {
 /// This object contains information about the chat whose identifier was shared with the bot
 /// using a [KeyboardButtonRequestChat](https://core.telegram.org/bots/api#keyboardbuttonrequestchat) button.
 /// https://core.telegram.org/bots/api#chatshared
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ChatShared {
     pub request_id: i32,
     pub chat_id: i64,
@@ -1146,12 +1166,12 @@ pub struct ChatShared {
 /// This object represents a service message about a user allowing a bot added to the attachment menu to write messages.
 /// Currently holds no information.
 /// https://core.telegram.org/bots/api#writeaccessallowed
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct WriteAccessAllowed;
 
 /// Describes Telegram Passport data shared with the bot by the user.
 /// https://core.telegram.org/bots/api#passportdata
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PassportData {
     pub data: Vec<EncryptedPassportElement>,
     pub credentials: EncryptedCredentials,
@@ -1160,7 +1180,7 @@ pub struct PassportData {
 /// Describes documents or other Telegram Passport elements shared with the bot by the user.
 /// https://core.telegram.org/bots/api#encryptedpassportelement
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EncryptedPassportElement {
     pub element_type: PassportElementType,
     pub data: Option<CompactString>,
@@ -1177,7 +1197,7 @@ pub struct EncryptedPassportElement {
 /// This object represents a file uploaded to Telegram Passport.
 /// Currently all Telegram Passport files are in JPEG format when decrypted and don't exceed 10MB.
 /// https://core.telegram.org/bots/api#passportfile
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PassportFile {
     pub file_id: CompactString,
     pub file_unique_id: CompactString,
@@ -1188,7 +1208,7 @@ pub struct PassportFile {
 /// Element type. One of “personal_details”, “passport”, “driver_license”, “identity_card”,
 /// “internal_passport”, “address”, “utility_bill”, “bank_statement”, “rental_agreement”,
 /// “passport_registration”, “temporary_registration”, “phone_number”, “email”.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum PassportElementType {
     PersonalDetails,
@@ -1208,7 +1228,7 @@ pub enum PassportElementType {
 
 /// Describes documents or other Telegram Passport elements shared with the bot by the user.
 /// https://core.telegram.org/bots/api#encryptedpassportelement
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EncryptedCredentials {
     pub data: CompactString,
     pub hash: CompactString,
@@ -1218,7 +1238,7 @@ pub struct EncryptedCredentials {
 /// https://core.telegram.org/bots/api#proximityalerttriggered
 /// This object represents the content of a service message,
 /// sent whenever a user in the chat triggers a proximity alert set by another user.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ProximityAlertTriggered {
     pub traveler: User,
     pub watcher: User,
@@ -1228,7 +1248,7 @@ pub struct ProximityAlertTriggered {
 /// This object represents a service message about a new forum topic created in the chat.
 /// https://core.telegram.org/bots/api#forumtopiccreated
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ForumTopicCreated {
     pub name: CompactString,
     pub icon_color: i32,
@@ -1238,7 +1258,7 @@ pub struct ForumTopicCreated {
 /// This object represents a service message about an edited forum topic.
 /// https://core.telegram.org/bots/api#forumtopicedited
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ForumTopicEdited {
     pub name: Option<CompactString>,
     pub icon_color: i32,
@@ -1248,29 +1268,29 @@ pub struct ForumTopicEdited {
 /// This object represents a service message about a forum topic closed in the chat.
 /// Currently holds no information.
 /// https://core.telegram.org/bots/api#forumtopicclosed
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ForumTopicClosed;
 
 /// This object represents a service message about a forum topic reopened in the chat.
 /// Currently holds no information.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ForumTopicReopened;
 
 /// This object represents a service message about General forum topic hidden in the chat.
 /// Currently holds no information.
 /// https://core.telegram.org/bots/api#forumtopichidden
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GeneralForumTopicHidden;
 
 /// This object represents a service message about General forum topic unhidden in the chat.
 /// Currently holds no information.
 /// https://core.telegram.org/bots/api#forumtopicunhidden
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GeneralForumTopicUnhidden;
 
 /// This object represents a service message about a video chat scheduled in the chat.
 /// https://core.telegram.org/bots/api#videochatscheduled
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VideoChatScheduled {
     pub start_date: Timestamp,
 }
@@ -1278,26 +1298,26 @@ pub struct VideoChatScheduled {
 /// This object represents a service message about a video chat started in the chat.
 /// Currently holds no information.
 /// https://core.telegram.org/bots/api#videochatstarted
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VideoChatStarted;
 
 /// This object represents a service message about a video chat ended in the chat.
 /// https://core.telegram.org/bots/api#videochatended
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VideoChatEnded {
     pub duration: i32,
 }
 
 /// This object represents a service message about new members invited to a video chat.
 /// https://core.telegram.org/bots/api#videochatparticipantsinvited
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VideoChatParticipantsInvited {
     pub users: Vec<User>,
 }
 
 /// Describes data sent from a [Web App](https://core.telegram.org/bots/webapps) to the bot.
 /// https://core.telegram.org/bots/api#webappdata
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct WebAppData {
     pub data: CompactString,
     pub button_text: CompactString,
@@ -1306,7 +1326,7 @@ pub struct WebAppData {
 /// This object represents an [inline keyboard](https://core.telegram.org/bots/features#inline-keyboards)
 /// that appears right next to the message it belongs to.
 /// https://core.telegram.org/bots/api#inlinekeyboardmarkup
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct InlineKeyboardMarkup {
     pub inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
 }
@@ -1315,7 +1335,7 @@ pub struct InlineKeyboardMarkup {
 /// You **must** use exactly one of the optional fields.
 /// https://core.telegram.org/bots/api#inlinekeyboardbutton
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct InlineKeyboardButton {
     pub text: CompactString,
     pub url: Option<CompactString>,
@@ -1333,7 +1353,7 @@ pub struct InlineKeyboardButton {
 /// All the user needs to do is tap/click a button and confirm that they want to log in.
 /// https://core.telegram.org/bots/api#loginurl
 #[skip_serializing_none]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct LoginUrl {
     pub url: CompactString,
     pub forward_text: Option<CompactString>,
@@ -1343,12 +1363,12 @@ pub struct LoginUrl {
 
 /// A placeholder, currently holds no information. Use BotFather to set up your game.
 /// https://core.telegram.org/bots/api#callbackgame
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CallbackGame {}
 
 /// This object represents a phone contact.
 /// https://core.telegram.org/bots/api#contact
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Contact {
     pub phone_number: CompactString,
     pub first_name: CompactString,
@@ -1359,7 +1379,7 @@ pub struct Contact {
 
 pub static DELETED_ACCOUNT: &str = "Deleted Account";
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Message {
     pub message_id: MessageId,
     pub message_thread_id: Option<i64>,
@@ -1454,7 +1474,7 @@ impl Message {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ChatAction {
     Typing,
@@ -1470,7 +1490,7 @@ pub enum ChatAction {
     UploadVideoNote,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct WebhookInfo {
     pub url: CompactString,
     pub has_custom_certificate: bool,
@@ -1,5 +1,5 @@
 use crate::{
-    basic_types::{MessageId, MessageThreadId},
+    basic_types::{MessageId, MessageThreadId, UserId},
     files::{Files, GetFiles},
 };
 use compact_str::CompactString;
@@ -8,7 +8,7 @@ use serde::Serialize;
 use serde_with::skip_serializing_none;
 
 use crate::proto::{
-    ChatAction, ChatId, InputFile, MessageEntity, ParseMode, ReplyMarkup, UpdateType,
+    BotCommand, ChatAction, ChatId, InputFile, MessageEntity, ParseMode, ReplyMarkup, UpdateType,
 };
 
 #[skip_serializing_none]
@@ -178,3 +178,23 @@ pub struct SendAnimationRequest {
     pub allow_sending_without_reply: Option<bool>,
     pub reply_markup: Option<ReplyMarkup>,
 }
+
+/// Use this method to change the list of the bot's commands. Returns True on success.
+/// https://core.telegram.org/bots/api#setmycommands
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct SetMyCommandsRequest {
+    pub commands: Vec<BotCommand>,
+    /// A two-letter ISO 639-1 language code. If empty, commands will be applied to all users
+    /// from the given scope, for whose language there are no dedicated commands
+    pub language_code: Option<CompactString>,
+}
+
+/// Use this method to get information about a member of a chat.
+/// Returns a ChatMember object on success.
+/// https://core.telegram.org/bots/api#getchatmember
+#[derive(Debug, Serialize)]
+pub struct GetChatMemberRequest {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+}
@@ -0,0 +1,380 @@
+use crate::bot::command::BotCommandInfo;
+use api::proto::{Message, MessageEntityType, User};
+use chrono::{Datelike, NaiveDate};
+use compact_str::{CompactString, ToCompactString};
+use derive_more::Display;
+use eyre::{bail, eyre};
+use std::{ops::Range, time::Duration};
+
+/// Wrong command arguments, the bot replies with it instead of logging a module failure
+#[derive(Debug)]
+pub struct UsageError {
+    pub reason: CompactString,
+    /// Command with its arguments usage, e.g. `/set <dd.mm>`
+    pub usage: Option<CompactString>,
+}
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.usage {
+            Some(usage) => write!(f, "{}\nUsage: {usage}", self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Command argument parsed from a single word or a quoted string
+pub trait Arg: Sized {
+    fn parse_arg(token: &str) -> eyre::Result<Self>;
+}
+
+macro_rules! impl_arg_from_str {
+    ($($t:ty),*) => {
+        $(
+            impl Arg for $t {
+                fn parse_arg(token: &str) -> eyre::Result<Self> {
+                    Ok(token.parse::<$t>()?)
+                }
+            }
+        )*
+    };
+}
+
+impl_arg_from_str!(i32, i64, u32, u64, usize, f64, bool);
+
+impl Arg for CompactString {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        Ok(token.into())
+    }
+}
+
+impl Arg for String {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        Ok(token.into())
+    }
+}
+
+/// Numbers with units `s`, `m`, `h`, `d`, `w` like `1h30m`, bare numbers are seconds
+impl Arg for Duration {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        if let Ok(seconds) = token.parse::<u64>() {
+            return Ok(Duration::from_secs(seconds));
+        }
+        let mut total = 0u64;
+        let mut number = String::new();
+        for c in token.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let unit = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => bail!("unknown duration unit '{c}'"),
+            };
+            if number.is_empty() {
+                bail!("no number before '{c}'");
+            }
+            total = number
+                .parse::<u64>()?
+                .checked_mul(unit)
+                .and_then(|seconds| total.checked_add(seconds))
+                .ok_or_else(|| eyre!("duration is too long"))?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            bail!("no unit after {number}");
+        }
+        Ok(Duration::from_secs(total))
+    }
+}
+
+/// `on` or `off`, for settings that are turned on and off
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Switch(pub bool);
+
+impl Arg for Switch {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        match token.to_lowercase().as_str() {
+            "on" => Ok(Switch(true)),
+            "off" => Ok(Switch(false)),
+            _ => bail!("expected on or off"),
+        }
+    }
+}
+
+/// `dd.mm.yyyy` or `yyyy-mm-dd`
+impl Arg for NaiveDate {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        Ok(NaiveDate::parse_from_str(token, "%d.%m.%Y")
+            .or_else(|_| NaiveDate::parse_from_str(token, "%Y-%m-%d"))?)
+    }
+}
+
+/// Date without a year, e.g. a birthday, `dd.mm`
+#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, Hash)]
+#[display(fmt = "{day:02}.{month:02}")]
+pub struct DayMonth {
+    pub day: u32,
+    pub month: u32,
+}
+
+impl DayMonth {
+    /// None for 29.02 in a non-leap year
+    pub fn in_year(self, year: i32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, self.month, self.day)
+    }
+}
+
+impl Arg for DayMonth {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        let Some((day, month)) = token.split_once('.') else {
+            bail!("expected dd.mm");
+        };
+        let date = DayMonth {
+            day: day.parse()?,
+            month: month.parse()?,
+        };
+        // 2000 is a leap year, so 29.02 is valid
+        if date.in_year(2000).is_none() {
+            bail!("no such date");
+        }
+        Ok(date)
+    }
+}
+
+impl From<NaiveDate> for DayMonth {
+    fn from(date: NaiveDate) -> Self {
+        Self {
+            day: date.day(),
+            month: date.month(),
+        }
+    }
+}
+
+/// User given as a mention or by replying to their message
+#[derive(Debug, Clone)]
+pub enum UserRef {
+    /// `@username`
+    Username(CompactString),
+    /// Text mention of a user without a username or the author of the replied message
+    User(User),
+}
+
+/// Command query split into arguments, words in double quotes are a single argument
+pub struct Args<'a> {
+    cmd: &'a BotCommandInfo,
+    message: &'a Message,
+    tokens: Vec<(CompactString, Range<usize>)>,
+    next: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(cmd: &'a BotCommandInfo, message: &'a Message) -> eyre::Result<Self> {
+        let tokens = tokenize(cmd.query()).map_err(|reason| usage_error(cmd, reason))?;
+        Ok(Self {
+            cmd,
+            message,
+            tokens,
+            next: 0,
+        })
+    }
+
+    pub fn required<T: Arg>(&mut self, name: &str) -> eyre::Result<T> {
+        match self.optional(name)? {
+            Some(value) => Ok(value),
+            None => Err(self.error(format!("{name} is missing"))),
+        }
+    }
+
+    pub fn optional<T: Arg>(&mut self, name: &str) -> eyre::Result<Option<T>> {
+        let Some((token, _)) = self.tokens.get(self.next) else {
+            return Ok(None);
+        };
+        let value = T::parse_arg(token)
+            .map_err(|err| self.error(format!("'{token}' is not a valid {name}, {err}")))?;
+        self.next += 1;
+        Ok(Some(value))
+    }
+
+    /// The rest of the query as is, empty if nothing is left
+    pub fn rest(&mut self) -> CompactString {
+        let Some((_, range)) = self.tokens.get(self.next) else {
+            return CompactString::default();
+        };
+        self.next = self.tokens.len();
+        self.cmd.query()[range.start..].trim().into()
+    }
+
+    /// Mentioned user or the author of the replied message
+    pub fn user(&mut self, name: &str) -> eyre::Result<UserRef> {
+        if let Some((token, _)) = self.tokens.get(self.next) {
+            if let Some(user) = self.text_mention(token) {
+                self.next += 1;
+                return Ok(UserRef::User(user));
+            }
+            if let Some(username) = token.strip_prefix('@').filter(|u| !u.is_empty()) {
+                self.next += 1;
+                return Ok(UserRef::Username(username.into()));
+            }
+        }
+        self.message
+            .reply_to_message
+            .as_ref()
+            .and_then(|reply| reply.from.clone())
+            .map(UserRef::User)
+            .ok_or_else(|| self.error(format!("mention {name} or reply to their message")))
+    }
+
+    /// Fails if any arguments are left
+    pub fn finish(self) -> eyre::Result<()> {
+        match self.tokens.get(self.next) {
+            Some((token, _)) => Err(self.error(format!("unexpected '{token}'"))),
+            None => Ok(()),
+        }
+    }
+
+    fn text_mention(&self, token: &str) -> Option<User> {
+        let text = self.message.text.as_ref()?;
+        let utf16 = text.encode_utf16().collect::<Vec<_>>();
+        self.message
+            .entities
+            .iter()
+            .flatten()
+            .filter(|e| e.entity_type == MessageEntityType::TextMention)
+            .find(|e| {
+                let start = e.offset.max(0) as usize;
+                utf16
+                    .get(start..start + e.length)
+                    .map(String::from_utf16_lossy)
+                    .is_some_and(|mention| mention.trim() == token)
+            })
+            .and_then(|e| e.user.clone())
+    }
+
+    fn error(&self, reason: String) -> eyre::Report {
+        usage_error(self.cmd, reason)
+    }
+}
+
+fn usage_error(cmd: &BotCommandInfo, reason: impl ToCompactString) -> eyre::Report {
+    eyre::Report::new(UsageError {
+        reason: reason.to_compact_string(),
+        usage: cmd
+            .usage()
+            .map(|usage| format!("/{} {usage}", cmd.name()).into()),
+    })
+}
+
+/// Splits on whitespace, keeping double-quoted parts whole, `\"` is a quote inside them
+fn tokenize(query: &str) -> Result<Vec<(CompactString, Range<usize>)>, &'static str> {
+    let mut tokens = vec![];
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut token = CompactString::default();
+        let mut end = query.len();
+        if c == '"' {
+            chars.next();
+            let mut closed = false;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' if chars.peek().is_some_and(|&(_, next)| next == '"') => {
+                        token.push('"');
+                        chars.next();
+                    }
+                    '"' => {
+                        end = i + 1;
+                        closed = true;
+                        break;
+                    }
+                    c => token.push(c),
+                }
+            }
+            if !closed {
+                return Err("unterminated quote");
+            }
+        } else {
+            while let Some(&(i, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push((token, start..end));
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> (BotCommandInfo, Message) {
+        let message = Message {
+            text: Some(text.into()),
+            ..Default::default()
+        };
+        let cmd = BotCommandInfo::try_from(&message)
+            .unwrap()
+            .with_usage(Some("<args>".into()));
+        (cmd, message)
+    }
+
+    #[test]
+    fn quoted_and_rest_arguments() {
+        let (cmd, message) = command(r#"cmd "two words" 42 "say \"hi\"" the  rest"#);
+        let mut args = Args::new(&cmd, &message).unwrap();
+        assert_eq!(args.required::<CompactString>("a").unwrap(), "two words");
+        assert_eq!(args.required::<i64>("b").unwrap(), 42);
+        assert_eq!(args.required::<String>("c").unwrap(), r#"say "hi""#);
+        assert_eq!(args.rest(), "the  rest");
+        assert_eq!(args.optional::<u32>("d").unwrap(), None);
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn usage_errors() {
+        let (cmd, message) = command("cmd abc extra");
+        let mut args = Args::new(&cmd, &message).unwrap();
+        let err = args.required::<i64>("chat id").unwrap_err();
+        let usage = err.downcast_ref::<UsageError>().unwrap();
+        assert_eq!(usage.usage.as_deref(), Some("/cmd <args>"));
+        assert!(usage.reason.contains("not a valid chat id"));
+
+        let (cmd, message) = command(r#"cmd "open"#);
+        assert!(Args::new(&cmd, &message).is_err());
+    }
+
+    #[test]
+    fn durations_and_dates() {
+        assert_eq!(
+            Duration::parse_arg("1h30m").unwrap(),
+            Duration::from_secs(90 * 60)
+        );
+        assert_eq!(Duration::parse_arg("45").unwrap(), Duration::from_secs(45));
+        assert!(Duration::parse_arg("10x").is_err());
+        assert!(Duration::parse_arg("31000000000000w").is_err());
+        assert!(Duration::parse_arg("18446744073709551615s1s").is_err());
+        assert_eq!(
+            NaiveDate::parse_arg("01.02.2024").unwrap(),
+            NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()
+        );
+        assert_eq!(
+            DayMonth::parse_arg("29.02").unwrap(),
+            DayMonth { day: 29, month: 2 }
+        );
+        assert!(DayMonth::parse_arg("31.02").is_err());
+    }
+}
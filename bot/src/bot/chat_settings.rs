@@ -0,0 +1,234 @@
+use crate::{bot::args::Arg, persistence::Persistence};
+use api::basic_types::{ChatIntId, UserId};
+use bincode::{Decode, Encode};
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::RwLock,
+};
+
+/// Which messages are taken as commands, `/command` always is
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct TriggerPolicy {
+    /// Prefix working as the slash, e.g. `!` for `!pls cats`
+    pub prefix: Option<String>,
+    /// The first word of any message, e.g. `pls cats`
+    pub bare_words: bool,
+    /// `@bot pls cats` and `pls cats` in reply to the bot
+    pub mention: bool,
+}
+
+/// Bare words are on, as they were before the triggers could be chosen per chat
+impl Default for TriggerPolicy {
+    fn default() -> Self {
+        Self {
+            prefix: None,
+            bare_words: true,
+            mention: true,
+        }
+    }
+}
+
+impl TriggerPolicy {
+    /// Everything is a command, as if every message was addressed to the bot
+    pub fn any() -> Self {
+        Self {
+            prefix: None,
+            bare_words: true,
+            mention: true,
+        }
+    }
+}
+
+impl fmt::Display for TriggerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on_off = |flag| if flag { "on" } else { "off" };
+        write!(
+            f,
+            "slash: on\nprefix: {}\nbare words: {}\nmention: {}",
+            self.prefix.as_deref().unwrap_or("off"),
+            on_off(self.bare_words),
+            on_off(self.mention)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct ChatSettings {
+    pub triggers: TriggerPolicy,
+    /// Modules turned off in the chat by its admins
+    pub disabled_modules: HashSet<String>,
+    /// Module name to its settings changed in the chat
+    pub modules: HashMap<String, ModuleSettings>,
+    /// Role name to the users given it in the chat
+    pub roles: HashMap<String, HashSet<UserId>>,
+}
+
+impl ChatSettings {
+    pub fn is_enabled(&self, module: &str) -> bool {
+        !self.disabled_modules.contains(module)
+    }
+
+    pub fn has_role(&self, role: &str, user_id: UserId) -> bool {
+        self.roles
+            .get(role)
+            .is_some_and(|users| users.contains(&user_id))
+    }
+
+    pub fn module(&self, module: &str) -> ModuleSettings {
+        self.modules.get(module).cloned().unwrap_or_default()
+    }
+}
+
+/// Setting of a module chat admins may change with `/config`
+#[derive(Debug, Clone)]
+pub struct SettingSpec {
+    pub key: CompactString,
+    pub description: CompactString,
+    validate: fn(&str) -> eyre::Result<()>,
+}
+
+impl SettingSpec {
+    /// Values are checked to parse as `T` before they are set
+    pub fn new<T: Arg>(key: &str, description: &str) -> Self {
+        Self {
+            key: key.into(),
+            description: description.into(),
+            validate: |value| T::parse_arg(value).map(drop),
+        }
+    }
+
+    pub fn validate(&self, value: &str) -> eyre::Result<()> {
+        (self.validate)(value)
+    }
+}
+
+/// Settings of a module in a chat, those not set are up to the module
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct ModuleSettings(HashMap<String, String>);
+
+impl ModuleSettings {
+    /// Fails if the value was set but does not parse,
+    /// e.g. after the module changed the setting type
+    pub fn get<T: Arg>(&self, key: &str) -> eyre::Result<Option<T>> {
+        self.0
+            .get(key)
+            .map(|value| {
+                T::parse_arg(value)
+                    .map_err(|err| eyre::eyre!("invalid chat setting {key} = '{value}', {err}"))
+            })
+            .transpose()
+    }
+
+    pub fn raw(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.0.insert(key.into(), value.into());
+            }
+            None => {
+                self.0.remove(key);
+            }
+        }
+    }
+}
+
+/// Settings changed by chat admins, chats that changed nothing use the defaults
+#[derive(Debug, Default)]
+pub struct ChatSettingsStore {
+    defaults: ChatSettings,
+    chats: RwLock<HashMap<ChatIntId, ChatSettings>>,
+}
+
+impl ChatSettingsStore {
+    pub fn new(defaults: ChatSettings) -> Self {
+        Self {
+            defaults,
+            chats: Default::default(),
+        }
+    }
+
+    pub fn get(&self, chat_id: ChatIntId) -> ChatSettings {
+        self.chats
+            .read()
+            .expect("chat settings lock poisoned")
+            .get(&chat_id)
+            .unwrap_or(&self.defaults)
+            .clone()
+    }
+
+    /// Returns the settings after the update
+    pub fn update(&self, chat_id: ChatIntId, f: impl FnOnce(&mut ChatSettings)) -> ChatSettings {
+        let mut chats = self.chats.write().expect("chat settings lock poisoned");
+        let settings = chats
+            .entry(chat_id)
+            .or_insert_with(|| self.defaults.clone());
+        f(settings);
+        settings.clone()
+    }
+}
+
+impl Persistence for ChatSettingsStore {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn serialize(&self) -> eyre::Result<Self::Output> {
+        let chats = self.chats.read().expect("chat settings lock poisoned");
+        Ok(bincode::encode_to_vec(
+            &*chats,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn deserialize(&mut self, input: Self::Input) -> eyre::Result<()> {
+        let chats = bincode::decode_from_slice::<HashMap<ChatIntId, ChatSettings>, _>(
+            input.as_slice(),
+            bincode::config::standard(),
+        )?
+        .0;
+        self.chats = RwLock::new(chats);
+        Ok(())
+    }
+
+    fn export(&self) -> eyre::Result<serde_json::Value> {
+        let chats = self.chats.read().expect("chat settings lock poisoned");
+        Ok(serde_json::to_value(&*chats)?)
+    }
+
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        self.chats = RwLock::new(serde_json::from_value(value)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_persistence() {
+        let store = ChatSettingsStore::default();
+        store.update(1, |settings| {
+            settings.disabled_modules.insert("imager".into());
+            settings
+                .modules
+                .entry("gigachat".into())
+                .or_default()
+                .set("limit", Some("10"));
+        });
+        let mut loaded = ChatSettingsStore::default();
+        loaded.deserialize(store.serialize().unwrap()).unwrap();
+
+        let settings = loaded.get(1);
+        assert!(!settings.is_enabled("imager"));
+        let gigachat = settings.module("gigachat");
+        assert_eq!(gigachat.get::<usize>("limit").unwrap(), Some(10));
+        assert!(gigachat.get::<bool>("limit").is_err());
+        assert!(loaded.get(2).is_enabled("imager"));
+    }
+}
@@ -1,4 +1,11 @@
-use api::proto::{Message, MessageEntity, MessageEntityType};
+use crate::bot::{
+    args::Args,
+    chat_settings::{ModuleSettings, TriggerPolicy},
+};
+use api::{
+    basic_types::UserId,
+    proto::{Message, MessageEntity, MessageEntityType},
+};
 use compact_str::CompactString;
 use eyre::bail;
 
@@ -6,19 +13,47 @@ use eyre::bail;
 pub struct BotCommandInfo {
     name: CompactString,
     query: CompactString,
+    trigger: Trigger,
+    usage: Option<CompactString>,
+    settings: ModuleSettings,
+}
+
+/// How a command was called
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// `/pls cats` or `/pls@jab3 cats`
+    Slash,
+    /// `@jab3 pls cats`
+    Mention,
+    /// `!pls cats` with `!` set as the chat command prefix
+    Prefix,
+    /// `pls cats` in reply to a message of the bot
+    Reply,
+    /// `pls cats`
+    BareWord,
+}
+
+/// The bot account, tells commands addressed to the bot from those for other bots
+#[derive(Debug, Clone, Default)]
+pub struct BotIdentity {
+    pub id: UserId,
+    /// Empty if unknown, then commands for any bot are accepted
+    pub username: CompactString,
+}
+
+impl BotIdentity {
+    fn is_me(&self, username: &str) -> bool {
+        self.username.is_empty() || self.username.eq_ignore_ascii_case(username)
+    }
 }
 
 impl TryFrom<&Message> for BotCommandInfo {
     type Error = eyre::Report;
 
     fn try_from(message: &Message) -> Result<Self, Self::Error> {
-        let Some(text) = message.text.as_ref() else {
-            bail!("no text for bot command in {message:?}");
-        };
-        if let Some(entity) = message.is_of_entity(MessageEntityType::BotCommand) {
-            Ok(Self::from_command(text, entity))
-        } else {
-            Ok(Self::from_text(text))
+        match Self::parse(message, &BotIdentity::default(), &TriggerPolicy::any()) {
+            Some(cmd) => Ok(cmd),
+            None => bail!("no bot command in {message:?}"),
         }
     }
 }
@@ -32,24 +67,387 @@ impl BotCommandInfo {
         &self.query
     }
 
-    fn from_command(text: &CompactString, bot_command_entity: MessageEntity) -> Self {
-        let (cmd, query) = text.split_at(bot_command_entity.length);
-        let cmd = cmd
-            .strip_prefix('/')
-            .and_then(|c| c.split('@').next())
-            .unwrap_or(cmd);
-        Self {
-            name: cmd.into(),
-            query: query.into(),
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+
+    /// None if there is no text, the command is addressed to another bot
+    /// or it is called in a way the chat has turned off
+    pub fn parse(message: &Message, me: &BotIdentity, triggers: &TriggerPolicy) -> Option<Self> {
+        let text = message.text.as_ref()?;
+        if let Some(entity) = message
+            .is_of_entity(MessageEntityType::BotCommand)
+            .filter(|entity| entity.offset == 0)
+        {
+            return Self::from_command(text, entity, me);
+        }
+        // Telegram marks only latin commands, `/плс` comes without an entity
+        if let Some(rest) = text.strip_prefix('/') {
+            return Self::from_text(rest, Trigger::Slash, me);
+        }
+        if triggers.mention && !me.username.is_empty() {
+            if let Some(rest) = text
+                .strip_prefix('@')
+                .and_then(|rest| strip_prefix_ignore_case(rest, &me.username))
+                .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            {
+                let rest = rest.trim_start();
+                return Self::from_text(
+                    rest.strip_prefix('/').unwrap_or(rest),
+                    Trigger::Mention,
+                    me,
+                );
+            }
+        }
+        if let Some(rest) = triggers
+            .prefix
+            .as_deref()
+            .filter(|prefix| !prefix.is_empty())
+            .and_then(|prefix| text.strip_prefix(prefix))
+        {
+            return Self::from_text(rest, Trigger::Prefix, me);
+        }
+        let replied_to_me = me.id != 0
+            && message
+                .reply_to_message
+                .as_ref()
+                .and_then(|reply| reply.from.as_ref())
+                .is_some_and(|user| user.id == me.id);
+        if triggers.mention && replied_to_me {
+            Self::from_text(text, Trigger::Reply, me)
+        } else if triggers.bare_words {
+            Self::from_text(text, Trigger::BareWord, me)
+        } else {
+            None
         }
     }
 
-    fn from_text(text: &CompactString) -> Self {
-        let (cmd, query) = text.split_once(' ').unwrap_or((text.as_str(), ""));
-        let cmd = cmd.split('@').next().unwrap_or(cmd);
-        Self {
-            name: cmd.into(),
-            query: query.into(),
+    /// Arguments usage of the command as declared by its module
+    pub fn usage(&self) -> Option<&CompactString> {
+        self.usage.as_ref()
+    }
+
+    /// Splits the query into typed arguments,
+    /// fails with [`UsageError`](crate::bot::args::UsageError) on unterminated quotes
+    pub fn args<'a>(&'a self, message: &'a Message) -> eyre::Result<Args<'a>> {
+        Args::new(self, message)
+    }
+
+    /// Replaces an alias the command was called by with the command name
+    pub(crate) fn with_name(mut self, name: CompactString) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Settings of the module in the chat the command is called in
+    pub fn settings(&self) -> &ModuleSettings {
+        &self.settings
+    }
+
+    pub(crate) fn with_settings(mut self, settings: ModuleSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub(crate) fn with_usage(mut self, usage: Option<CompactString>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    fn from_command(
+        text: &str,
+        bot_command_entity: MessageEntity,
+        me: &BotIdentity,
+    ) -> Option<Self> {
+        let (cmd, query) = text.split_at(bot_command_entity.length.min(text.len()));
+        let cmd = cmd.strip_prefix('/').unwrap_or(cmd);
+        Self::new(cmd, query, Trigger::Slash, me)
+    }
+
+    fn from_text(text: &str, trigger: Trigger, me: &BotIdentity) -> Option<Self> {
+        let (cmd, query) = text.split_once(' ').unwrap_or((text, ""));
+        Self::new(cmd, query, trigger, me)
+    }
+
+    /// `cmd` may be addressed as `cmd@bot`
+    fn new(cmd: &str, query: &str, trigger: Trigger, me: &BotIdentity) -> Option<Self> {
+        let name = match cmd.split_once('@') {
+            Some((name, username)) if me.is_me(username) => name,
+            Some(_) => return None,
+            None => cmd,
+        };
+        Some(Self {
+            name: name.into(),
+            query: query.trim_start().into(),
+            trigger,
+            usage: None,
+            settings: Default::default(),
+        })
+    }
+}
+
+/// Keys of the same place on QWERTY and ЙЦУКЕН keyboards
+const LATIN_LAYOUT: &str = "`qwertyuiop[]asdfghjkl;'zxcvbnm,.";
+const CYRILLIC_LAYOUT: &str = "ёйцукенгшщзхъфывапролджэячсмитьбю";
+
+/// Names a command may have been meant by if it was typed in the wrong keyboard layout
+/// or transliterated, e.g. `зды` and `плс` for `pls`, tried when the name itself is unknown
+pub fn mistyped_names(name: &str) -> Vec<CompactString> {
+    let name = name.to_lowercase();
+    let mut names = vec![];
+    if name.chars().any(is_cyrillic) {
+        // a latin `a` typed among cyrillic letters is most likely the cyrillic one
+        let cyrillic = name
+            .chars()
+            .map(latin_homoglyph_to_cyrillic)
+            .collect::<String>();
+        names.push(switch_layout(&cyrillic, CYRILLIC_LAYOUT, LATIN_LAYOUT));
+        names.push(transliterate(&cyrillic, cyrillic_to_latin));
+    } else if name.chars().any(|c| c.is_ascii_alphabetic()) {
+        names.push(switch_layout(&name, LATIN_LAYOUT, CYRILLIC_LAYOUT));
+        names.push(transliterate(&name, latin_to_cyrillic));
+    }
+    names.retain(|candidate| *candidate != name);
+    names.dedup();
+    names
+}
+
+fn is_cyrillic(c: char) -> bool {
+    matches!(c, 'а'..='я' | 'ё')
+}
+
+fn switch_layout(text: &str, from: &str, to: &str) -> CompactString {
+    text.chars()
+        .map(|c| match from.chars().position(|key| key == c) {
+            Some(index) => to.chars().nth(index).unwrap_or(c),
+            None => c,
+        })
+        .collect()
+}
+
+/// Characters without a transliteration are kept as is
+fn transliterate(text: &str, letter: fn(char) -> Option<&'static str>) -> CompactString {
+    let mut result = CompactString::default();
+    for c in text.chars() {
+        match letter(c) {
+            Some(latin) => result.push_str(latin),
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+fn latin_homoglyph_to_cyrillic(c: char) -> char {
+    match c {
+        'a' => 'а',
+        'c' => 'с',
+        'e' => 'е',
+        'k' => 'к',
+        'o' => 'о',
+        'p' => 'р',
+        'x' => 'х',
+        'y' => 'у',
+        c => c,
+    }
+}
+
+fn cyrillic_to_latin(c: char) -> Option<&'static str> {
+    match c {
+        'а' => Some("a"),
+        'б' => Some("b"),
+        'в' => Some("v"),
+        'г' => Some("g"),
+        'д' => Some("d"),
+        'е' | 'э' => Some("e"),
+        'ё' => Some("yo"),
+        'ж' => Some("zh"),
+        'з' => Some("z"),
+        'и' => Some("i"),
+        'й' | 'ы' => Some("y"),
+        'к' => Some("k"),
+        'л' => Some("l"),
+        'м' => Some("m"),
+        'н' => Some("n"),
+        'о' => Some("o"),
+        'п' => Some("p"),
+        'р' => Some("r"),
+        'с' => Some("s"),
+        'т' => Some("t"),
+        'у' => Some("u"),
+        'ф' => Some("f"),
+        'х' => Some("h"),
+        'ц' => Some("ts"),
+        'ч' => Some("ch"),
+        'ш' => Some("sh"),
+        'щ' => Some("sch"),
+        'ъ' | 'ь' => Some(""),
+        'ю' => Some("yu"),
+        'я' => Some("ya"),
+        _ => None,
+    }
+}
+
+fn latin_to_cyrillic(c: char) -> Option<&'static str> {
+    match c {
+        'a' => Some("а"),
+        'b' => Some("б"),
+        'c' => Some("ц"),
+        'd' => Some("д"),
+        'e' => Some("е"),
+        'f' => Some("ф"),
+        'g' => Some("г"),
+        'h' => Some("х"),
+        'i' => Some("и"),
+        'j' => Some("й"),
+        'k' | 'q' => Some("к"),
+        'l' => Some("л"),
+        'm' => Some("м"),
+        'n' => Some("н"),
+        'o' => Some("о"),
+        'p' => Some("п"),
+        'r' => Some("р"),
+        's' => Some("с"),
+        't' => Some("т"),
+        'u' => Some("у"),
+        'v' | 'w' => Some("в"),
+        'x' => Some("кс"),
+        'y' => Some("ы"),
+        'z' => Some("з"),
+        _ => None,
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::proto::User;
+
+    fn me() -> BotIdentity {
+        BotIdentity {
+            id: 1,
+            username: "jab3".into(),
         }
     }
+
+    fn slash_command(text: &str, length: usize) -> Message {
+        Message {
+            text: Some(text.into()),
+            entities: Some(vec![MessageEntity {
+                entity_type: MessageEntityType::BotCommand,
+                offset: 0,
+                length,
+                url: None,
+                user: None,
+                language: None,
+                custom_emoji_id: None,
+            }]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn commands_for_other_bots_are_ignored() {
+        let cmd = BotCommandInfo::parse(
+            &slash_command("/pls@Jab3 cats", 9),
+            &me(),
+            &TriggerPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(cmd.name(), "pls");
+        assert_eq!(cmd.query(), "cats");
+        assert_eq!(cmd.trigger(), Trigger::Slash);
+        assert!(BotCommandInfo::parse(
+            &slash_command("/pls@OtherBot cats", 13),
+            &me(),
+            &TriggerPolicy::default(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn mention_and_reply_triggers() {
+        let message = Message {
+            text: Some("@jab3 pls cats".into()),
+            ..Default::default()
+        };
+        let cmd = BotCommandInfo::parse(&message, &me(), &TriggerPolicy::default()).unwrap();
+        assert_eq!(
+            (cmd.name().as_str(), cmd.trigger()),
+            ("pls", Trigger::Mention)
+        );
+        assert_eq!(cmd.query(), "cats");
+
+        let message = Message {
+            text: Some("pls dogs".into()),
+            reply_to_message: Some(Box::new(Message {
+                from: Some(User {
+                    id: 1,
+                    is_bot: true,
+                    first_name: "jab".into(),
+                    last_name: None,
+                    username: Some("jab3".into()),
+                    language_code: None,
+                    is_premium: None,
+                    added_to_attachment_menu: None,
+                    can_join_groups: None,
+                    can_read_all_group_messages: None,
+                    supports_inline_queries: None,
+                }),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let cmd = BotCommandInfo::parse(&message, &me(), &TriggerPolicy::default()).unwrap();
+        assert_eq!(
+            (cmd.name().as_str(), cmd.trigger()),
+            ("pls", Trigger::Reply)
+        );
+    }
+
+    #[test]
+    fn disabled_triggers_are_not_commands() {
+        let message = |text: &str| Message {
+            text: Some(text.into()),
+            ..Default::default()
+        };
+        let slash_only = TriggerPolicy {
+            prefix: None,
+            bare_words: false,
+            mention: false,
+        };
+        assert!(BotCommandInfo::parse(&message("pls cats"), &me(), &slash_only).is_none());
+        assert!(BotCommandInfo::parse(&message("@jab3 pls cats"), &me(), &slash_only).is_none());
+
+        let prefix = TriggerPolicy {
+            prefix: Some("!".into()),
+            bare_words: false,
+            ..Default::default()
+        };
+        let cmd = BotCommandInfo::parse(&message("!pls cats"), &me(), &prefix).unwrap();
+        assert_eq!(
+            (cmd.name().as_str(), cmd.trigger()),
+            ("pls", Trigger::Prefix)
+        );
+        assert!(BotCommandInfo::parse(&message("pls cats"), &me(), &prefix).is_none());
+
+        let cmd =
+            BotCommandInfo::parse(&message("pls cats"), &me(), &TriggerPolicy::any()).unwrap();
+        assert_eq!(cmd.trigger(), Trigger::BareWord);
+    }
+
+    #[test]
+    fn mistyped_layout_and_transliteration() {
+        assert!(mistyped_names("зды").contains(&"pls".into()));
+        assert!(mistyped_names("пшa").contains(&"gif".into()));
+        assert!(mistyped_names("гиф1").contains(&"gif1".into()));
+        assert!(mistyped_names("gkc").contains(&"плс".into()));
+        assert!(mistyped_names("gpt").contains(&"гпт".into()));
+        assert!(mistyped_names("42").is_empty());
+    }
 }
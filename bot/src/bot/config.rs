@@ -1,29 +1,60 @@
-use crate::connector::ConnectorMode;
-use api::proto::UpdateType;
+use crate::{
+    bot::{chat_settings::TriggerPolicy, middleware::FilterConfig, supervisor::SupervisorConfig},
+    connector::{config::ConnectorConfig, health::BackoffConfig},
+    storage::StorageBackend,
+};
+use api::basic_types::UserId;
 use compact_str::CompactString;
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, path::PathBuf, time::Duration};
 
 #[derive(Debug)]
 pub struct BotConfig {
-    pub allowed_updates: HashSet<UpdateType>,
-    pub update_limit: Option<u32>,
-    pub polling_timeout: Option<u32>,
-    pub skip_missed_updates: bool,
+    pub connector: ConnectorConfig,
     pub work_dir: PathBuf,
     pub data_file_name: CompactString,
-    pub connector_mode: ConnectorMode,
+    /// How often the data is saved besides on shutdown, none to save on shutdown only
+    pub autosave_interval: Option<Duration>,
+    /// Number of previous data files kept by the file storage, one is taken per start and day,
+    /// the data is loaded from them if the file is broken
+    pub backups: usize,
+    pub storage: StorageBackend,
+    /// Journal replayed instead of fetching updates, the bot stops once it is handled;
+    /// neither the data nor the update offset is saved then
+    pub journal: Option<PathBuf>,
+    /// Messages are logged instead of sent, see [`crate::communicator::DryRunCommunicator`]
+    pub dry_run: bool,
+    /// The data files are neither written nor moved aside if broken, nor restored
+    /// from the backups, e.g. to export the data; always so when replaying a journal
+    pub read_only: bool,
+    /// Retry policy for failed update fetching
+    pub backoff: BackoffConfig,
+    /// Timeouts and failure limits of module handlers
+    pub supervisor: SupervisorConfig,
+    /// How commands are called in chats whose admins have not changed it
+    pub triggers: TriggerPolicy,
+    /// Updates that never reach the modules
+    pub filters: FilterConfig,
+    /// Users allowed to call any command in any chat
+    pub owners: HashSet<UserId>,
 }
 
 impl Default for BotConfig {
     fn default() -> Self {
         Self {
-            allowed_updates: Default::default(),
-            update_limit: None,
-            polling_timeout: None,
-            skip_missed_updates: false,
+            connector: Default::default(),
             work_dir: Default::default(),
             data_file_name: "jab.data".into(),
-            connector_mode: Default::default(),
+            autosave_interval: Some(Duration::from_secs(5 * 60)),
+            backups: 3,
+            storage: Default::default(),
+            journal: None,
+            dry_run: false,
+            read_only: false,
+            backoff: Default::default(),
+            supervisor: Default::default(),
+            triggers: Default::default(),
+            filters: Default::default(),
+            owners: Default::default(),
         }
     }
 }
@@ -0,0 +1,1380 @@
+use crate::{
+    bot::{
+        args::UserRef,
+        args::{Arg, Switch, UsageError},
+        chat_settings::{ChatSettings, ChatSettingsStore, TriggerPolicy},
+        command::{mistyped_names, BotCommandInfo, BotIdentity},
+        message_to_string,
+        middleware::{update_chat_id, Flow, Middleware, UpdateContext},
+        registry::{CommandRegistry, CommandSpec, Permission},
+        supervisor::SupervisedModule,
+        throttle::{format_wait, Throttle},
+        SharedModule,
+    },
+    communicator::{del, Communicate, SharedCommunicator},
+    module::Module,
+};
+use api::{
+    basic_types::{ChatIntId, UpdateId, UserId},
+    proto::{ChatType, CommonUpdate, Message, Update},
+};
+use compact_str::CompactString;
+use eyre::bail;
+use futures_util::future::join_all;
+use log::{debug, error};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Write,
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// Handles updates of different chats concurrently,
+/// updates of the same chat are handled one by one in the order received
+pub(crate) struct Dispatcher {
+    handler: Arc<Handler>,
+    chats: HashMap<ChatIntId, ChatQueue>,
+    done_tx: UnboundedSender<Done>,
+    in_flight: BTreeSet<UpdateId>,
+    last_dispatched: UpdateId,
+}
+
+struct ChatQueue {
+    tx: UnboundedSender<CommonUpdate>,
+    pending: usize,
+}
+
+/// Sent by a chat worker once an update is handled
+#[derive(Debug)]
+pub(crate) struct Done {
+    pub chat_id: Option<ChatIntId>,
+    pub update_id: UpdateId,
+}
+
+impl Dispatcher {
+    pub fn new(
+        handler: Arc<Handler>,
+        done_tx: UnboundedSender<Done>,
+        last_dispatched: UpdateId,
+    ) -> Self {
+        Self {
+            handler,
+            chats: Default::default(),
+            done_tx,
+            in_flight: Default::default(),
+            last_dispatched,
+        }
+    }
+
+    pub fn is_old_update(&self, id: UpdateId) -> bool {
+        if self.last_dispatched != 0 && self.last_dispatched < id - 1 {
+            error!(
+                "some updates skipped! last update id = {}, new update id = {}",
+                self.last_dispatched, id
+            );
+        }
+        self.last_dispatched >= id
+    }
+
+    pub fn dispatch(&mut self, update: CommonUpdate) {
+        self.in_flight.insert(update.id);
+        self.last_dispatched = self.last_dispatched.max(update.id);
+
+        let Some(chat_id) = update_chat_id(&update.data) else {
+            let handler = self.handler.clone();
+            let done_tx = self.done_tx.clone();
+            tokio::spawn(async move {
+                let update_id = update.id;
+                handle_isolated(handler, update).await;
+                let _ = done_tx.send(Done {
+                    chat_id: None,
+                    update_id,
+                });
+            });
+            return;
+        };
+
+        let queue = self
+            .chats
+            .entry(chat_id)
+            .or_insert_with(|| spawn_chat_worker(chat_id, self.handler.clone(), &self.done_tx));
+        queue.pending += 1;
+        if let Err(err) = queue.tx.send(update) {
+            error!("chat {chat_id} worker died, update #{} dropped", err.0.id);
+            // the next update of the chat gets a new worker
+            self.chats.remove(&chat_id);
+            self.in_flight.remove(&err.0.id);
+        }
+    }
+
+    /// Returns the id all the updates up to which are handled
+    pub fn on_done(&mut self, done: Done) -> UpdateId {
+        self.in_flight.remove(&done.update_id);
+        if let Some(chat_id) = done.chat_id {
+            if let Some(queue) = self.chats.get_mut(&chat_id) {
+                queue.pending = queue.pending.saturating_sub(1);
+                if queue.pending == 0 {
+                    // the worker stops once its queue sender is dropped
+                    self.chats.remove(&chat_id);
+                }
+            }
+        }
+        self.handled_up_to()
+    }
+
+    pub fn handled_up_to(&self) -> UpdateId {
+        match self.in_flight.first() {
+            Some(first) => first - 1,
+            None => self.last_dispatched,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
+
+fn spawn_chat_worker(
+    chat_id: ChatIntId,
+    handler: Arc<Handler>,
+    done_tx: &UnboundedSender<Done>,
+) -> ChatQueue {
+    let (tx, mut rx) = unbounded_channel::<CommonUpdate>();
+    let done_tx = done_tx.clone();
+    tokio::spawn(async move {
+        while let Some(update) = rx.recv().await {
+            let update_id = update.id;
+            handle_isolated(handler.clone(), update).await;
+            let _ = done_tx.send(Done {
+                chat_id: Some(chat_id),
+                update_id,
+            });
+        }
+    });
+    ChatQueue { tx, pending: 0 }
+}
+
+/// Handles the update in a task of its own, so that a panic outside the modules,
+/// e.g. in a middleware, cannot kill the worker and leave the update in flight forever
+async fn handle_isolated(handler: Arc<Handler>, update: CommonUpdate) {
+    let update_id = update.id;
+    if let Err(err) = tokio::spawn(async move { handler.handle_update(update).await }).await {
+        error!("failed to handle update #{update_id}, {err}");
+    }
+}
+
+/// Everything needed to handle an update, shared by all the chat workers
+pub(crate) struct Handler {
+    pub communicator: SharedCommunicator,
+    pub me: BotIdentity,
+    pub modules: Vec<SupervisedModule>,
+    pub commands: CommandRegistry,
+    pub chat_settings: Arc<ChatSettingsStore>,
+    pub throttle: Arc<Throttle>,
+    pub middlewares: Vec<Box<dyn Middleware>>,
+    /// Users allowed to call any command
+    pub owners: HashSet<UserId>,
+    /// Whether to tell the chat that its command failed
+    pub reply_on_error: bool,
+}
+
+impl Handler {
+    async fn handle_update(&self, update: CommonUpdate) {
+        let mut ctx = UpdateContext::new(update.id);
+        let mut entered = 0;
+        for middleware in &self.middlewares {
+            entered += 1;
+            match middleware
+                .before(self.communicator.as_ref(), &update, &mut ctx)
+                .await
+            {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Stop) => {
+                    debug!("update #{} stopped by '{}'", update.id, middleware.name());
+                    ctx.stopped_by = Some(middleware.name().into());
+                    break;
+                }
+                Err(err) => error!(
+                    "middleware '{}' failed on update #{}, {err}",
+                    middleware.name(),
+                    update.id
+                ),
+            }
+        }
+        if ctx.stopped_by.is_none() {
+            self.route_update(&update).await;
+        }
+        for middleware in self.middlewares[..entered].iter().rev() {
+            if let Err(err) = middleware
+                .after(self.communicator.as_ref(), &update, &ctx)
+                .await
+            {
+                error!(
+                    "middleware '{}' failed after update #{}, {err}",
+                    middleware.name(),
+                    update.id
+                );
+            }
+        }
+    }
+
+    async fn route_update(&self, update: &CommonUpdate) {
+        match &update.data {
+            Update::MessageUpdate(msg)
+            | Update::EditedMessageUpdate(msg)
+            | Update::ChannelPostUpdate(msg)
+            | Update::EditedChannelPostUpdate(msg) => {
+                debug!(
+                    "update #{} message received: {}",
+                    update.id,
+                    message_to_string(msg)
+                );
+            }
+            _ => {
+                debug!("update received: {update:?}");
+            }
+        }
+        match &update.data {
+            Update::MessageUpdate(message) => {
+                if let Err(report) = self.handle_message_update(message.clone()).await {
+                    error!("{}", report);
+                }
+            }
+            data => {
+                let context = format!("update #{}", update.id);
+                let data = Arc::new(data.clone());
+                let chat_id = update_chat_id(&data);
+                self.run_modules(chat_id, &context, |module, comm| {
+                    let data = data.clone();
+                    async move { call_update_hook(module.as_ref(), comm.as_ref(), &data).await }
+                })
+                .await;
+            }
+        };
+    }
+
+    pub async fn on_startup(&self) {
+        self.run_modules(None, "startup", |module, comm| async move {
+            module.on_startup(comm.as_ref()).await
+        })
+        .await;
+    }
+
+    pub async fn on_shutdown(&self) {
+        self.run_modules(None, "shutdown", |module, comm| async move {
+            module.on_shutdown(comm.as_ref()).await
+        })
+        .await;
+    }
+
+    /// Runs the hook for every module enabled in the chat to completion,
+    /// a failing one does not cancel the rest, returns the number of failed modules
+    async fn run_modules<F, Fut>(
+        &self,
+        chat_id: Option<ChatIntId>,
+        context: &str,
+        make_hook: F,
+    ) -> usize
+    where
+        F: Fn(SharedModule, SharedCommunicator) -> Fut,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let settings = chat_id.map(|chat_id| self.chat_settings.get(chat_id));
+        let results = join_all(
+            self.modules
+                .iter()
+                .filter(|m| settings.as_ref().is_none_or(|s| s.is_enabled(m.name())))
+                .map(|m| async { (m.name(), m.run(&self.communicator, &make_hook).await) }),
+        )
+        .await;
+
+        let mut failed = 0;
+        for (name, result) in results {
+            if let Err(err) = result {
+                error!("module '{name}' failed on {context}, {err}");
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    async fn handle_message_update(&self, message: Message) -> eyre::Result<()> {
+        let settings = self.chat_settings.get(message.chat.id);
+        let command = BotCommandInfo::parse(&message, &self.me, &settings.triggers)
+            .and_then(|cmd| {
+                let (module, spec) = self.resolve_command(cmd.name())?;
+                Some((module, spec, cmd))
+            })
+            // commands of disabled modules are just messages
+            .filter(|(module, _, _)| *module == JAB_MODULE_NAME || settings.is_enabled(module));
+        let Some((module_name, spec, cmd)) = command else {
+            let context = format!("message in chat {}", message.chat.id);
+            let message = Arc::new(message);
+            self.run_modules(Some(message.chat.id), &context, |module, comm| {
+                let message = message.clone();
+                async move { module.on_message(comm.as_ref(), &message).await }
+            })
+            .await;
+            return Ok(());
+        };
+
+        if !spec.is_available_in(message.chat.chat_type) {
+            debug!(
+                "'{}' is not available in {:?} chats",
+                spec.name, message.chat.chat_type
+            );
+            return Ok(());
+        }
+        if !self
+            .is_permitted(&spec.permission, &settings, &message)
+            .await?
+        {
+            self.communicator
+                .reply_message(
+                    &format!("This command is for {} only", spec.permission),
+                    message.chat.id.into(),
+                    message.message_id,
+                    None,
+                )
+                .await?
+                .into_result()?;
+            return Ok(());
+        }
+
+        if !spec.limits.is_empty() {
+            let user_id = message.from.as_ref().map(|user| user.id);
+            if let Err(wait) =
+                self.throttle
+                    .check(&spec.name, &spec.limits, message.chat.id, user_id)
+            {
+                debug!("'{}' throttled in chat {}", spec.name, message.chat.id);
+                self.communicator
+                    .reply_message(
+                        &format!("Not so fast, try again in {}", format_wait(wait)),
+                        message.chat.id.into(),
+                        message.message_id,
+                        None,
+                    )
+                    .await?
+                    .into_result()?;
+                return Ok(());
+            }
+        }
+
+        let cmd = cmd
+            .with_name(spec.name.clone())
+            .with_usage(spec.usage.clone())
+            .with_settings(settings.module(module_name));
+        if module_name == JAB_MODULE_NAME {
+            return self.execute_jab_command(&cmd, &message).await;
+        }
+        let Some(module) = self.modules.iter().find(|m| m.name() == module_name) else {
+            bail!(
+                "module '{module_name}' of command '{}' not found",
+                cmd.name()
+            );
+        };
+
+        let context = format!("'{}' in chat {}", cmd.name(), message.chat.id);
+        let cmd = Arc::new(cmd);
+        let message = Arc::new(message);
+        let result = module
+            .run(&self.communicator, |module, comm| {
+                let cmd = cmd.clone();
+                let message = message.clone();
+                async move {
+                    module
+                        .try_execute_command(comm.as_ref(), &cmd, &message)
+                        .await
+                }
+            })
+            .await;
+
+        if let Err(err) = result {
+            if let Some(usage) = err.downcast_ref::<UsageError>() {
+                debug!("wrong arguments of {context}, {usage}");
+                self.communicator
+                    .reply_message(
+                        &usage.to_string(),
+                        message.chat.id.into(),
+                        message.message_id,
+                        None,
+                    )
+                    .await?
+                    .into_result()?;
+                return Ok(());
+            }
+            error!("module '{module_name}' failed on {context}, {err}");
+            if self.reply_on_error {
+                self.communicator
+                    .reply_message(
+                        "Sorry, something went wrong, please try again later",
+                        message.chat.id.into(),
+                        message.message_id,
+                        None,
+                    )
+                    .await?
+                    .into_result()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn execute_jab_command(
+        &self,
+        cmd: &BotCommandInfo,
+        message: &Message,
+    ) -> eyre::Result<()> {
+        match JabCommandName::from_str(cmd.name())? {
+            JabCommandName::Del => {
+                del(self.communicator.as_ref(), message).await?;
+            }
+            JabCommandName::Help => {
+                self.communicator
+                    .reply_message(
+                        &self.commands.help(message.chat.chat_type, |module| {
+                            module == JAB_MODULE_NAME
+                                || self.chat_settings.get(message.chat.id).is_enabled(module)
+                        }),
+                        message.chat.id.into(),
+                        message.message_id,
+                        None,
+                    )
+                    .await?
+                    .into_result()?;
+            }
+            JabCommandName::Triggers => {
+                let triggers = self.change_triggers(cmd, message)?;
+                self.communicator
+                    .reply_message(
+                        &format!("Commands in this chat\n{triggers}"),
+                        message.chat.id.into(),
+                        message.message_id,
+                        None,
+                    )
+                    .await?
+                    .into_result()?;
+            }
+            JabCommandName::Enable | JabCommandName::Disable => {
+                let mut args = cmd.args(message)?;
+                let module = args.required::<CompactString>("module")?;
+                args.finish()?;
+                let reply = if self.modules.iter().any(|m| m.name() == module) {
+                    let enable = cmd.name() == "enable";
+                    self.chat_settings.update(message.chat.id, |settings| {
+                        if enable {
+                            settings.disabled_modules.remove(module.as_str());
+                        } else {
+                            settings.disabled_modules.insert(module.to_string());
+                        }
+                    });
+                    format!("{module} is {}d in this chat", cmd.name())
+                } else {
+                    format!("No module '{module}', see /config for the list")
+                };
+                self.communicator
+                    .reply_message(&reply, message.chat.id.into(), message.message_id, None)
+                    .await?
+                    .into_result()?;
+            }
+            JabCommandName::Grant | JabCommandName::Revoke => {
+                let mut args = cmd.args(message)?;
+                let role = args.required::<CompactString>("role")?;
+                let user = match args.user("the user")? {
+                    UserRef::User(user) => user,
+                    UserRef::Username(_) => {
+                        return Err(eyre::Report::new(UsageError {
+                            reason: "@username cannot be told from others, reply to their message"
+                                .into(),
+                            usage: cmd.usage().map(|u| format!("/{} {u}", cmd.name()).into()),
+                        }));
+                    }
+                };
+                args.finish()?;
+                let grant = cmd.name() == "grant";
+                self.chat_settings.update(message.chat.id, |settings| {
+                    let users = settings.roles.entry(role.to_string()).or_default();
+                    if grant {
+                        users.insert(user.id);
+                    } else {
+                        users.remove(&user.id);
+                        if users.is_empty() {
+                            settings.roles.remove(role.as_str());
+                        }
+                    }
+                });
+                let reply = if grant {
+                    format!("{} is {role} now", user.first_name)
+                } else {
+                    format!("{} is not {role} anymore", user.first_name)
+                };
+                self.communicator
+                    .reply_message(&reply, message.chat.id.into(), message.message_id, None)
+                    .await?
+                    .into_result()?;
+            }
+            JabCommandName::Roles => {
+                let settings = self.chat_settings.get(message.chat.id);
+                let mut reply = String::from("Roles in this chat");
+                let mut roles = settings.roles.iter().collect::<Vec<_>>();
+                roles.sort_by_key(|(role, _)| role.as_str());
+                for (role, users) in roles {
+                    let users = users.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+                    let _ = write!(reply, "\n{role}: {}", users.join(", "));
+                }
+                if settings.roles.is_empty() {
+                    reply.push_str("\nnone");
+                }
+                self.communicator
+                    .reply_message(&reply, message.chat.id.into(), message.message_id, None)
+                    .await?
+                    .into_result()?;
+            }
+            JabCommandName::Config => {
+                let reply = self.configure_module(cmd, message)?;
+                self.communicator
+                    .reply_message(&reply, message.chat.id.into(), message.message_id, None)
+                    .await?
+                    .into_result()?;
+            }
+        };
+        Ok(())
+    }
+
+    /// Falls back to the names the command might have been mistyped as
+    fn resolve_command(&self, name: &str) -> Option<(&str, &CommandSpec)> {
+        self.commands.resolve(name).or_else(|| {
+            mistyped_names(name).iter().find_map(|mistyped| {
+                let resolved = self.commands.resolve(mistyped)?;
+                debug!("'{name}' resolved as mistyped '{}'", resolved.1.name);
+                Some(resolved)
+            })
+        })
+    }
+
+    /// Lists the modules, the settings of one or changes one of them,
+    /// returns the reply
+    fn configure_module(&self, cmd: &BotCommandInfo, message: &Message) -> eyre::Result<String> {
+        let chat_id = message.chat.id;
+        let mut args = cmd.args(message)?;
+        let Some(name) = args.optional::<CompactString>("module")? else {
+            let settings = self.chat_settings.get(chat_id);
+            let mut reply = String::from("Modules in this chat");
+            for module in &self.modules {
+                let state = if settings.is_enabled(module.name()) {
+                    "on"
+                } else {
+                    "off"
+                };
+                let _ = write!(reply, "\n{}: {state}", module.name());
+            }
+            return Ok(reply);
+        };
+        let Some(module) = self.modules.iter().find(|m| m.name() == name) else {
+            return Ok(format!("No module '{name}', see /config for the list"));
+        };
+        let specs = module.settings();
+        if let Some(key) = args.optional::<CompactString>("setting")? {
+            let Some(spec) = specs.iter().find(|spec| spec.key == key) else {
+                return Ok(format!("{name} has no setting '{key}'"));
+            };
+            let value = args.rest();
+            if value.is_empty() {
+                return Err(eyre::Report::new(UsageError {
+                    reason: "value is missing".into(),
+                    usage: Some(format!("/{} {name} {key} <value | reset>", cmd.name()).into()),
+                }));
+            }
+            let value = (value != "reset").then_some(value);
+            if let Some(value) = &value {
+                if let Err(err) = spec.validate(value) {
+                    return Ok(format!("'{value}' is not a valid {key}, {err}"));
+                }
+            }
+            self.chat_settings.update(chat_id, |settings| {
+                settings
+                    .modules
+                    .entry(name.to_string())
+                    .or_default()
+                    .set(&key, value.as_deref());
+            });
+        }
+        args.finish()?;
+
+        if specs.is_empty() {
+            return Ok(format!("{name} has no settings"));
+        }
+        let settings = self.chat_settings.get(chat_id).module(&name);
+        let mut reply = format!("{name} settings");
+        for spec in specs {
+            let value = settings.raw(&spec.key).unwrap_or("not set");
+            let _ = write!(reply, "\n{} = {value} - {}", spec.key, spec.description);
+        }
+        Ok(reply)
+    }
+
+    /// Returns the chat triggers after the change, no arguments change nothing
+    fn change_triggers(
+        &self,
+        cmd: &BotCommandInfo,
+        message: &Message,
+    ) -> eyre::Result<TriggerPolicy> {
+        let mut args = cmd.args(message)?;
+        let Some(mode) = args.optional::<TriggerMode>("mode")? else {
+            return Ok(self.chat_settings.get(message.chat.id).triggers);
+        };
+        let mut triggers = self.chat_settings.get(message.chat.id).triggers;
+        match mode {
+            TriggerMode::Slash => {
+                triggers = TriggerPolicy {
+                    prefix: None,
+                    bare_words: false,
+                    mention: false,
+                }
+            }
+            TriggerMode::Prefix => triggers.prefix = args.required::<CommandPrefix>("prefix")?.0,
+            TriggerMode::Bare => triggers.bare_words = args.required::<Switch>("switch")?.0,
+            TriggerMode::Mention => triggers.mention = args.required::<Switch>("switch")?.0,
+        }
+        args.finish()?;
+        self.chat_settings.update(message.chat.id, |settings| {
+            settings.triggers = triggers.clone()
+        });
+        Ok(triggers)
+    }
+
+    async fn is_permitted(
+        &self,
+        permission: &Permission,
+        settings: &ChatSettings,
+        message: &Message,
+    ) -> eyre::Result<bool> {
+        let user_id = message.from.as_ref().map(|user| user.id);
+        if *permission == Permission::Anyone || user_id.is_some_and(|id| self.owners.contains(&id))
+        {
+            return Ok(true);
+        }
+        match permission {
+            Permission::Role(role) if user_id.is_some_and(|id| settings.has_role(role, id)) => {
+                Ok(true)
+            }
+            Permission::Role(_) | Permission::ChatAdmin => self.is_sent_by_admin(message).await,
+            Permission::Anyone | Permission::Owner => Ok(false),
+        }
+    }
+
+    async fn is_sent_by_admin(&self, message: &Message) -> eyre::Result<bool> {
+        if message.chat.chat_type == ChatType::Private {
+            return Ok(true);
+        }
+        // anonymous admins send messages on behalf of the chat
+        if message
+            .sender_chat
+            .as_ref()
+            .is_some_and(|chat| chat.id == message.chat.id)
+        {
+            return Ok(true);
+        }
+        let Some(user) = message.from.as_ref() else {
+            return Ok(false);
+        };
+        let member = self
+            .communicator
+            .get_chat_member(message.chat.id.into(), user.id)
+            .await?
+            .into_result()?;
+        Ok(member.is_admin())
+    }
+}
+
+async fn call_update_hook(
+    module: &dyn Module,
+    comm: &dyn Communicate,
+    update: &Update,
+) -> eyre::Result<()> {
+    match update {
+        Update::EditedMessageUpdate(message) | Update::EditedChannelPostUpdate(message) => {
+            module.on_edited_message(comm, message).await
+        }
+        Update::ChannelPostUpdate(post) => module.on_channel_post(comm, post).await,
+        Update::CallbackQueryUpdate(query) => module.on_callback_query(comm, query).await,
+        Update::InlineQueryUpdate(query) => module.on_inline_query(comm, query).await,
+        Update::MyChatMemberUpdate(update) | Update::ChatMemberUpdate(update) => {
+            module.on_chat_member(comm, update).await
+        }
+        Update::ChatJoinRequestUpdate(request) => module.on_join_request(comm, request).await,
+        Update::PollAnswerUpdate(answer) => module.on_poll_answer(comm, answer).await,
+        _ => Ok(()),
+    }
+}
+
+/// Name the built-in commands are registered under
+pub(crate) const JAB_MODULE_NAME: &str = "jab";
+
+pub(crate) fn jab_commands() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec::new("help", "list the commands"),
+        CommandSpec::new("del", "delete the replied message").admin_only(),
+        CommandSpec::new(
+            "triggers",
+            "show or change how commands are called in this chat",
+        )
+        .usage("[slash | prefix <prefix>|off | bare on|off | mention on|off]")
+        .admin_only(),
+        CommandSpec::new("enable", "turn a module on in this chat")
+            .usage("<module>")
+            .admin_only(),
+        CommandSpec::new("disable", "turn a module off in this chat")
+            .usage("<module>")
+            .admin_only(),
+        CommandSpec::new("config", "list the modules, show or change their settings")
+            .usage("[module] [setting] [value | reset]")
+            .admin_only(),
+        CommandSpec::new("grant", "give the role to the user in this chat")
+            .usage("<role>, in reply to the user")
+            .admin_only(),
+        CommandSpec::new("revoke", "take the role from the user in this chat")
+            .usage("<role>, in reply to the user")
+            .admin_only(),
+        CommandSpec::new("roles", "list the roles given in this chat").admin_only(),
+    ]
+}
+
+enum JabCommandName {
+    Del,
+    Help,
+    Triggers,
+    Enable,
+    Disable,
+    Config,
+    Grant,
+    Revoke,
+    Roles,
+}
+
+impl FromStr for JabCommandName {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "del" => Ok(JabCommandName::Del),
+            "help" => Ok(JabCommandName::Help),
+            "triggers" => Ok(JabCommandName::Triggers),
+            "enable" => Ok(JabCommandName::Enable),
+            "disable" => Ok(JabCommandName::Disable),
+            "config" => Ok(JabCommandName::Config),
+            "grant" => Ok(JabCommandName::Grant),
+            "revoke" => Ok(JabCommandName::Revoke),
+            "roles" => Ok(JabCommandName::Roles),
+            _ => {
+                bail!("jab failed to recognize '{s}' as a possible command");
+            }
+        }
+    }
+}
+
+/// What `/triggers` changes
+enum TriggerMode {
+    /// Only `/command`
+    Slash,
+    Prefix,
+    Bare,
+    Mention,
+}
+
+impl Arg for TriggerMode {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        match token.to_lowercase().as_str() {
+            "slash" => Ok(TriggerMode::Slash),
+            "prefix" => Ok(TriggerMode::Prefix),
+            "bare" => Ok(TriggerMode::Bare),
+            "mention" => Ok(TriggerMode::Mention),
+            _ => bail!("expected slash, prefix, bare or mention"),
+        }
+    }
+}
+
+/// A few symbols or `off`
+struct CommandPrefix(Option<String>);
+
+impl Arg for CommandPrefix {
+    fn parse_arg(token: &str) -> eyre::Result<Self> {
+        if token.eq_ignore_ascii_case("off") {
+            return Ok(CommandPrefix(None));
+        }
+        if token.starts_with('/') || token.chars().any(char::is_alphanumeric) {
+            bail!("prefix may not be a slash, letters or digits");
+        }
+        if token.chars().count() > 3 {
+            bail!("prefix may be 3 symbols long at most");
+        }
+        Ok(CommandPrefix(Some(token.into())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{module::PersistentModule, persistence::Persistence};
+    use api::{
+        basic_types::{MessageId, MessageThreadId},
+        proto::{
+            BotCommand, CallbackQuery, Chat, ChatAction, ChatId, ChatJoinRequest, ChatMember,
+            ChatMemberUpdated, InlineQuery, MessageEntity, ParseMode, PollAnswer, ReplyMarkup,
+            User,
+        },
+        response::{CommonResponse, MessageIdResponse},
+    };
+    use async_trait::async_trait;
+    use std::{sync::Mutex, time::Duration};
+    use tokio::sync::{mpsc::UnboundedReceiver, Semaphore};
+
+    /// Answers every request at once without sending anything, records the texts sent
+    #[derive(Default)]
+    struct TestComm {
+        /// Users `get_chat_member` tells as admins
+        admins: HashSet<UserId>,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl TestComm {
+        fn sent(&self, text: &str) -> eyre::Result<CommonResponse<Message>> {
+            self.sent.lock().unwrap().push(text.into());
+            Ok(CommonResponse::Ok(Default::default()))
+        }
+    }
+
+    #[async_trait]
+    impl Communicate for TestComm {
+        async fn send_message(
+            &self,
+            text: &str,
+            _chat_id: ChatId,
+        ) -> eyre::Result<CommonResponse<Message>> {
+            self.sent(text)
+        }
+
+        async fn reply_message(
+            &self,
+            text: &str,
+            _chat_id: ChatId,
+            _reply_to_message_id: MessageId,
+            _parse_mode: Option<ParseMode>,
+        ) -> eyre::Result<CommonResponse<Message>> {
+            self.sent(text)
+        }
+
+        async fn send_photo_url(
+            &self,
+            url: &str,
+            _chat_id: ChatId,
+            _reply_to_message_id: Option<MessageId>,
+        ) -> eyre::Result<CommonResponse<Message>> {
+            self.sent(url)
+        }
+
+        async fn send_animation_url(
+            &self,
+            url: &str,
+            _chat_id: ChatId,
+            _reply_to_message_id: Option<MessageId>,
+        ) -> eyre::Result<CommonResponse<Message>> {
+            self.sent(url)
+        }
+
+        async fn forward_message(
+            &self,
+            _to_chat_id: ChatId,
+            _from_chat_id: ChatId,
+            _message_id: MessageId,
+            _disable_notification: Option<bool>,
+            _protect_content: Option<bool>,
+        ) -> eyre::Result<CommonResponse<Message>> {
+            self.sent("forwarded")
+        }
+
+        async fn copy_message(
+            &self,
+            _chat_id: ChatId,
+            _message_thread_id: Option<MessageThreadId>,
+            _from_chat_id: ChatId,
+            _message_id: MessageId,
+            _caption: Option<CompactString>,
+            _parse_mode: Option<ParseMode>,
+            _caption_entities: Vec<MessageEntity>,
+            _disable_notification: Option<bool>,
+            _protect_content: Option<bool>,
+            _reply_to_message_id: Option<MessageId>,
+            _allow_sending_without_reply: Option<bool>,
+            _reply_markup: Option<ReplyMarkup>,
+        ) -> eyre::Result<CommonResponse<MessageIdResponse>> {
+            self.sent.lock().unwrap().push("copied".into());
+            Ok(CommonResponse::Ok(MessageIdResponse { message_id: 0 }))
+        }
+
+        async fn send_chat_action(
+            &self,
+            _chat_id: ChatId,
+            _message_thread_id: Option<MessageThreadId>,
+            _action: ChatAction,
+        ) -> eyre::Result<CommonResponse<bool>> {
+            Ok(CommonResponse::Ok(true))
+        }
+
+        async fn delete_message(
+            &self,
+            _chat_id: ChatId,
+            _message_id: MessageId,
+        ) -> eyre::Result<CommonResponse<bool>> {
+            Ok(CommonResponse::Ok(true))
+        }
+
+        async fn set_my_commands(
+            &self,
+            _commands: Vec<BotCommand>,
+        ) -> eyre::Result<CommonResponse<bool>> {
+            Ok(CommonResponse::Ok(true))
+        }
+
+        async fn get_me(&self) -> eyre::Result<CommonResponse<User>> {
+            Ok(CommonResponse::Ok(user(0)))
+        }
+
+        async fn get_chat_member(
+            &self,
+            _chat_id: ChatId,
+            user_id: UserId,
+        ) -> eyre::Result<CommonResponse<ChatMember>> {
+            let status = if self.admins.contains(&user_id) {
+                "administrator"
+            } else {
+                "member"
+            };
+            let member = serde_json::json!({
+                "status": status,
+                "user": user(user_id),
+                "can_be_edited": false,
+                "is_anonymous": false,
+                "can_manage_chat": true,
+                "can_delete_messages": true,
+                "can_manage_video_chats": true,
+                "can_restrict_members": true,
+                "can_promote_members": false,
+                "can_change_info": true,
+                "can_invite_users": true,
+            });
+            Ok(CommonResponse::Ok(serde_json::from_value(member)?))
+        }
+    }
+
+    /// Records the messages it gets as `<chat id> <text>`,
+    /// `slow` takes a while and `wait` waits for a permit of the gate
+    struct Recorder {
+        seen: Arc<Mutex<Vec<String>>>,
+        gate: Arc<Semaphore>,
+    }
+
+    impl Default for Recorder {
+        fn default() -> Self {
+            Self {
+                seen: Default::default(),
+                gate: Arc::new(Semaphore::new(0)),
+            }
+        }
+    }
+
+    impl Recorder {
+        fn record(&self, entry: String) {
+            self.seen.lock().unwrap().push(entry);
+        }
+    }
+
+    #[async_trait]
+    impl Module for Recorder {
+        fn commands(&self) -> Vec<CommandSpec> {
+            vec![]
+        }
+
+        async fn try_execute_command(
+            &self,
+            _comm: &dyn Communicate,
+            _cmd: &BotCommandInfo,
+            _message: &Message,
+        ) -> eyre::Result<()> {
+            Ok(())
+        }
+
+        async fn on_message(&self, _comm: &dyn Communicate, message: &Message) -> eyre::Result<()> {
+            let text = message.text.clone().unwrap_or_default();
+            match text.as_str() {
+                "slow" => tokio::time::sleep(Duration::from_millis(50)).await,
+                "wait" => self.gate.acquire().await?.forget(),
+                _ => {}
+            }
+            self.record(format!("{} {text}", message.chat.id));
+            Ok(())
+        }
+
+        async fn on_edited_message(
+            &self,
+            _comm: &dyn Communicate,
+            message: &Message,
+        ) -> eyre::Result<()> {
+            let text = message.text.clone().unwrap_or_default();
+            self.record(format!("edited {} {text}", message.chat.id));
+            Ok(())
+        }
+
+        async fn on_channel_post(
+            &self,
+            _comm: &dyn Communicate,
+            post: &Message,
+        ) -> eyre::Result<()> {
+            let text = post.text.clone().unwrap_or_default();
+            self.record(format!("post {} {text}", post.chat.id));
+            Ok(())
+        }
+
+        async fn on_callback_query(
+            &self,
+            _comm: &dyn Communicate,
+            query: &CallbackQuery,
+        ) -> eyre::Result<()> {
+            let data = query.data.clone().unwrap_or_default();
+            self.record(format!("callback {data}"));
+            Ok(())
+        }
+
+        async fn on_inline_query(
+            &self,
+            _comm: &dyn Communicate,
+            query: &InlineQuery,
+        ) -> eyre::Result<()> {
+            self.record(format!("inline {}", query.query));
+            Ok(())
+        }
+
+        async fn on_chat_member(
+            &self,
+            _comm: &dyn Communicate,
+            update: &ChatMemberUpdated,
+        ) -> eyre::Result<()> {
+            self.record(format!("member {}", update.chat.id));
+            Ok(())
+        }
+
+        async fn on_join_request(
+            &self,
+            _comm: &dyn Communicate,
+            request: &ChatJoinRequest,
+        ) -> eyre::Result<()> {
+            self.record(format!("join {}", request.chat.id));
+            Ok(())
+        }
+
+        async fn on_poll_answer(
+            &self,
+            _comm: &dyn Communicate,
+            _answer: &PollAnswer,
+        ) -> eyre::Result<()> {
+            self.record("poll answer".into());
+            Ok(())
+        }
+    }
+
+    impl Persistence for Recorder {
+        type Input = Vec<u8>;
+        type Output = Vec<u8>;
+
+        fn serialize(&self) -> eyre::Result<Self::Output> {
+            Ok(vec![])
+        }
+
+        fn deserialize(&mut self, _input: Self::Input) -> eyre::Result<()> {
+            Ok(())
+        }
+
+        fn export(&self) -> eyre::Result<serde_json::Value> {
+            Ok(serde_json::Value::Null)
+        }
+
+        fn import(&mut self, _value: serde_json::Value) -> eyre::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl PersistentModule for Recorder {}
+
+    /// Panics on `boom`
+    struct Panicking;
+
+    #[async_trait]
+    impl Middleware for Panicking {
+        fn name(&self) -> &str {
+            "panicking"
+        }
+
+        async fn before(
+            &self,
+            _comm: &dyn Communicate,
+            update: &CommonUpdate,
+            _ctx: &mut UpdateContext,
+        ) -> eyre::Result<Flow> {
+            if let Update::MessageUpdate(message) = &update.data {
+                if message.text.as_deref() == Some("boom") {
+                    panic!("middleware panicked");
+                }
+            }
+            Ok(Flow::Continue)
+        }
+    }
+
+    fn handler(recorder: Recorder) -> Handler {
+        let mut commands = CommandRegistry::default();
+        commands.register(JAB_MODULE_NAME, jab_commands());
+        commands.register("recorder", recorder.commands());
+        Handler {
+            communicator: Arc::new(TestComm::default()),
+            me: Default::default(),
+            modules: vec![SupervisedModule::new(
+                "recorder".into(),
+                Arc::new(recorder),
+                Default::default(),
+            )],
+            commands,
+            chat_settings: Default::default(),
+            throttle: Default::default(),
+            middlewares: vec![],
+            owners: Default::default(),
+            reply_on_error: false,
+        }
+    }
+
+    fn user(id: UserId) -> User {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "is_bot": false,
+            "first_name": format!("user {id}"),
+        }))
+        .unwrap()
+    }
+
+    fn message(chat_id: ChatIntId, text: &str) -> Message {
+        Message {
+            chat: Chat {
+                id: chat_id,
+                chat_type: ChatType::Supergroup,
+                ..Default::default()
+            },
+            text: Some(text.into()),
+            ..Default::default()
+        }
+    }
+
+    fn message_update(id: UpdateId, chat_id: ChatIntId, text: &str) -> CommonUpdate {
+        CommonUpdate {
+            id,
+            data: Update::MessageUpdate(message(chat_id, text)),
+        }
+    }
+
+    /// Returns the ids handled in the order they were reported
+    async fn wait_idle(
+        dispatcher: &mut Dispatcher,
+        done_rx: &mut UnboundedReceiver<Done>,
+    ) -> Vec<UpdateId> {
+        let mut handled = vec![];
+        while !dispatcher.is_idle() {
+            let done = tokio::time::timeout(Duration::from_secs(5), done_rx.recv())
+                .await
+                .expect("update is never done")
+                .unwrap();
+            handled.push(done.update_id);
+            dispatcher.on_done(done);
+        }
+        handled
+    }
+
+    #[tokio::test]
+    async fn updates_of_a_chat_are_handled_in_order() {
+        let recorder = Recorder::default();
+        let seen = recorder.seen.clone();
+        let (done_tx, mut done_rx) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(Arc::new(handler(recorder)), done_tx, 0);
+        for (id, chat_id, text) in [(1, 1, "slow"), (2, 2, "a"), (3, 1, "b"), (4, 1, "c")] {
+            dispatcher.dispatch(message_update(id, chat_id, text));
+        }
+        wait_idle(&mut dispatcher, &mut done_rx).await;
+
+        let seen = seen.lock().unwrap().clone();
+        // the other chat does not wait for the slow one
+        assert_eq!(seen[0], "2 a");
+        assert_eq!(seen[1..], ["1 slow", "1 b", "1 c"]);
+        assert_eq!(dispatcher.handled_up_to(), 4);
+    }
+
+    #[tokio::test]
+    async fn handled_up_to_waits_for_earlier_updates() {
+        let recorder = Recorder::default();
+        let gate = recorder.gate.clone();
+        let (done_tx, mut done_rx) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(Arc::new(handler(recorder)), done_tx, 10);
+        dispatcher.dispatch(message_update(11, 1, "wait"));
+        dispatcher.dispatch(message_update(12, 2, "a"));
+        assert_eq!(dispatcher.handled_up_to(), 10);
+
+        let done = done_rx.recv().await.unwrap();
+        assert_eq!(done.update_id, 12);
+        assert_eq!(dispatcher.on_done(done), 10);
+
+        gate.add_permits(1);
+        let done = done_rx.recv().await.unwrap();
+        assert_eq!(done.update_id, 11);
+        assert_eq!(dispatcher.on_done(done), 12);
+        assert!(dispatcher.is_idle());
+        assert!(dispatcher.is_old_update(12));
+    }
+
+    #[tokio::test]
+    async fn panicking_handler_does_not_stall_the_chat() {
+        let recorder = Recorder::default();
+        let seen = recorder.seen.clone();
+        let mut handler = handler(recorder);
+        handler.middlewares.push(Box::new(Panicking));
+        let (done_tx, mut done_rx) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(Arc::new(handler), done_tx, 0);
+        dispatcher.dispatch(message_update(1, 1, "boom"));
+        dispatcher.dispatch(message_update(2, 1, "a"));
+        assert_eq!(wait_idle(&mut dispatcher, &mut done_rx).await, [1, 2]);
+        assert_eq!(dispatcher.handled_up_to(), 2);
+
+        dispatcher.dispatch(message_update(3, 1, "b"));
+        wait_idle(&mut dispatcher, &mut done_rx).await;
+        assert_eq!(*seen.lock().unwrap(), ["1 a", "1 b"]);
+        assert_eq!(dispatcher.handled_up_to(), 3);
+    }
+
+    #[tokio::test]
+    async fn updates_reach_their_hooks() {
+        let recorder = Recorder::default();
+        let seen = recorder.seen.clone();
+        let (done_tx, mut done_rx) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(Arc::new(handler(recorder)), done_tx, 0);
+        let user = r#"{"id": 7, "is_bot": false, "first_name": "user"}"#;
+        let chat = r#"{"id": -1, "type": "supergroup"}"#;
+        let channel = r#"{"id": -2, "type": "channel"}"#;
+        let updates = [
+            format!(r#""message": {{"message_id": 1, "date": 0, "chat": {chat}, "text": "a"}}"#),
+            format!(
+                r#""edited_message": {{"message_id": 1, "date": 0, "chat": {chat}, "text": "b"}}"#
+            ),
+            format!(
+                r#""channel_post": {{"message_id": 2, "date": 0, "chat": {channel}, "text": "c"}}"#
+            ),
+            format!(
+                r#""edited_channel_post":
+                    {{"message_id": 2, "date": 0, "chat": {channel}, "text": "d"}}"#
+            ),
+            format!(r#""callback_query": {{"id": "q", "from": {user}, "data": "pressed"}}"#),
+            format!(
+                r#""inline_query": {{"id": "i", "from": {user}, "query": "cats", "offset": ""}}"#
+            ),
+            format!(
+                r#""my_chat_member": {{"chat": {chat}, "from": {user}, "date": 0,
+                    "old_chat_member": {{"status": "left", "user": {user}}},
+                    "new_chat_member": {{"status": "member", "user": {user}}}}}"#
+            ),
+            format!(
+                r#""chat_join_request":
+                    {{"chat": {chat}, "from": {user}, "user_chat_id": 7, "date": 0}}"#
+            ),
+            format!(r#""poll_answer": {{"poll_id": "p", "user": {user}, "option_ids": [0]}}"#),
+        ];
+        for (id, update) in updates.iter().enumerate() {
+            let json = format!(r#"{{"update_id": {}, {update}}}"#, id + 1);
+            dispatcher.dispatch(serde_json::from_str(&json).unwrap());
+        }
+        wait_idle(&mut dispatcher, &mut done_rx).await;
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(
+            seen,
+            [
+                "-1 a",
+                "callback pressed",
+                "edited -1 b",
+                "edited -2 d",
+                "inline cats",
+                "join -1",
+                "member -1",
+                "poll answer",
+                "post -2 c",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn permissions_are_checked() {
+        let mut handler = handler(Recorder::default());
+        handler.communicator = Arc::new(TestComm {
+            admins: HashSet::from([5]),
+            ..Default::default()
+        });
+        handler.owners = HashSet::from([1]);
+        let mut settings = ChatSettings::default();
+        settings.roles.insert("mod".into(), HashSet::from([3]));
+        let from = |user_id: UserId| Message {
+            from: Some(user(user_id)),
+            ..message(-1, "/cmd")
+        };
+        let permitted = |permission: Permission, message: Message| {
+            let handler = &handler;
+            let settings = &settings;
+            async move {
+                handler
+                    .is_permitted(&permission, settings, &message)
+                    .await
+                    .unwrap()
+            }
+        };
+        let role = || Permission::Role("mod".into());
+
+        assert!(permitted(Permission::Anyone, from(4)).await);
+        // owners may call anything
+        assert!(permitted(Permission::Owner, from(1)).await);
+        assert!(permitted(role(), from(1)).await);
+        assert!(permitted(Permission::ChatAdmin, from(1)).await);
+        assert!(!permitted(Permission::Owner, from(5)).await);
+        assert!(!permitted(Permission::Owner, from(3)).await);
+
+        assert!(permitted(role(), from(3)).await);
+        assert!(!permitted(Permission::ChatAdmin, from(3)).await);
+        // admins have every role
+        assert!(permitted(role(), from(5)).await);
+        assert!(permitted(Permission::ChatAdmin, from(5)).await);
+        assert!(!permitted(role(), from(4)).await);
+        assert!(!permitted(Permission::ChatAdmin, from(4)).await);
+
+        let mut private = from(4);
+        private.chat.chat_type = ChatType::Private;
+        assert!(permitted(Permission::ChatAdmin, private.clone()).await);
+        assert!(!permitted(Permission::Owner, private).await);
+
+        let on_behalf_of = |chat_id: ChatIntId| Message {
+            sender_chat: Some(Chat {
+                id: chat_id,
+                ..Default::default()
+            }),
+            ..message(-1, "/cmd")
+        };
+        assert!(permitted(Permission::ChatAdmin, on_behalf_of(-1)).await);
+        assert!(permitted(role(), on_behalf_of(-1)).await);
+        // a linked channel is not an admin
+        assert!(!permitted(Permission::ChatAdmin, on_behalf_of(-2)).await);
+        assert!(!permitted(Permission::Owner, on_behalf_of(-1)).await);
+    }
+}
@@ -0,0 +1,58 @@
+use api::basic_types::ChatIntId;
+use compact_str::CompactString;
+use log::error;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Events produced outside of update handling, e.g. by modules or timers
+#[derive(Debug)]
+pub enum Event {
+    SendMessage {
+        chat_id: ChatIntId,
+        text: CompactString,
+    },
+    SaveData,
+}
+
+/// Handle to push events into the bot main loop, cheap to clone
+#[derive(Clone)]
+pub struct EventSender {
+    tx: UnboundedSender<Event>,
+}
+
+impl EventSender {
+    pub(crate) fn new(tx: UnboundedSender<Event>) -> Self {
+        Self { tx }
+    }
+
+    pub fn send(&self, event: Event) {
+        if let Err(err) = self.tx.send(event) {
+            error!("failed to send {:?}, bot is not running", err.0);
+        }
+    }
+
+    /// Delivers the event once after the delay
+    pub fn send_after(&self, delay: Duration, event: Event) {
+        let sender = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            sender.send(event);
+        });
+    }
+
+    /// Delivers a new event produced by `make_event` every `period`
+    /// until the bot stops
+    pub fn send_every(&self, period: Duration, make_event: impl Fn() -> Event + Send + 'static) {
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if tx.send(make_event()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,218 @@
+use crate::connector::{
+    health::{Backoff, ConnectorHealth},
+    Connector,
+};
+use api::{basic_types::UpdateId, proto::CommonUpdate, response::ErrorResponse};
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc::Sender, watch},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+/// Fetches updates in the background so that the main loop never waits on a long poll
+pub(crate) struct Fetcher {
+    pub connector: Box<dyn Connector>,
+    pub backoff: Backoff,
+    pub health_tx: watch::Sender<ConnectorHealth>,
+    /// Last update id handled by the bot, fetching starts after it
+    pub committed_rx: watch::Receiver<UpdateId>,
+}
+
+impl Fetcher {
+    pub fn spawn(mut self, updates_tx: Sender<Vec<CommonUpdate>>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(1000));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            let mut last_fetched = *self.committed_rx.borrow_and_update();
+
+            loop {
+                // only the handled updates are committed, Telegram drops the committed ones,
+                // so the next long poll would return those in flight at once; it waits for
+                // some of them to be handled instead
+                if *self.committed_rx.borrow_and_update() < last_fetched
+                    && self.committed_rx.changed().await.is_err()
+                {
+                    break;
+                }
+                let committed = *self.committed_rx.borrow_and_update();
+                if committed != 0 {
+                    self.connector.commit(committed);
+                }
+
+                if self.connector.is_exhausted() {
+                    // the bot stops once the health changes, so it has to handle what it got first
+                    while *self.committed_rx.borrow_and_update() < last_fetched {
+                        if self.committed_rx.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                    self.set_health(ConnectorHealth::Exhausted);
+                    break;
+                }
+                let updates = match self.connector.fetch_updates().await {
+                    Ok(updates) => updates,
+                    Err(err) => match self.on_error(err) {
+                        Some(delay) => {
+                            tokio::time::sleep(delay).await;
+                            continue;
+                        }
+                        None => break,
+                    },
+                };
+
+                if self.backoff.failures() > 0 {
+                    info!(
+                        "fetching recovered after {} failures",
+                        self.backoff.failures()
+                    );
+                    self.backoff.reset();
+                }
+                self.set_health(ConnectorHealth::Healthy);
+
+                let updates = updates
+                    .into_iter()
+                    .filter(|u| u.id > last_fetched)
+                    .collect::<Vec<_>>();
+
+                if updates.is_empty() {
+                    interval.tick().await;
+                    continue;
+                }
+
+                last_fetched = updates.iter().map(|u| u.id).max().unwrap_or(last_fetched);
+                if updates_tx.send(updates).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Returns the delay before the next attempt, none if fetching should stop
+    fn on_error(&mut self, err: eyre::Report) -> Option<Duration> {
+        let response = err.downcast_ref::<ErrorResponse>();
+        match response.map(|r| r.error_code) {
+            Some(401) => {
+                error!("bot token was rejected, fetching stopped, {err}");
+                self.set_health(ConnectorHealth::Unauthorized);
+                None
+            }
+            Some(409) => {
+                let delay = self
+                    .backoff
+                    .next_delay()
+                    .max(self.backoff.config().conflict_delay);
+                warn!(
+                    "another instance is fetching updates with the same token, \
+                    retrying in {delay:?}, {err}"
+                );
+                self.set_health(ConnectorHealth::Conflict);
+                Some(delay)
+            }
+            _ => {
+                let mut delay = self.backoff.next_delay();
+                if let Some(retry_after) = response.and_then(|r| r.retry_after) {
+                    delay = delay.max(Duration::from_secs(retry_after.max(0) as u64));
+                }
+                if self.backoff.failures() == self.backoff.config().circuit_threshold {
+                    error!(
+                        "fetching failed {} times in a row, retrying every {delay:?} now, {err}",
+                        self.backoff.failures()
+                    );
+                } else {
+                    error!(
+                        "fetching failed, retrying in {delay:?} (attempt #{}), {err}",
+                        self.backoff.failures()
+                    );
+                }
+                self.set_health(self.backoff.health());
+                Some(delay)
+            }
+        }
+    }
+
+    fn set_health(&self, health: ConnectorHealth) {
+        self.health_tx.send_if_modified(|current| {
+            if *current == health {
+                false
+            } else {
+                *current = health;
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connector::health::BackoffConfig;
+    use api::proto::Update;
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Serves the batches one by one, then never returns
+    struct Batches {
+        batches: Vec<Vec<UpdateId>>,
+        commits: Arc<Mutex<Vec<UpdateId>>>,
+    }
+
+    #[async_trait]
+    impl Connector for Batches {
+        async fn on_startup(&mut self) -> eyre::Result<()> {
+            Ok(())
+        }
+
+        async fn fetch_updates(&mut self) -> eyre::Result<Vec<CommonUpdate>> {
+            if self.batches.is_empty() {
+                std::future::pending::<()>().await;
+            }
+            Ok(self
+                .batches
+                .remove(0)
+                .into_iter()
+                .map(|id| CommonUpdate {
+                    id,
+                    data: Update::MessageUpdate(Default::default()),
+                })
+                .collect())
+        }
+
+        fn commit(&mut self, update_id: UpdateId) {
+            self.commits.lock().unwrap().push(update_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn offset_moves_once_updates_are_handled() {
+        let commits = Arc::new(Mutex::new(vec![]));
+        let (health_tx, _health_rx) = watch::channel(ConnectorHealth::Starting);
+        let (committed_tx, committed_rx) = watch::channel(0);
+        let fetcher = Fetcher {
+            connector: Box::new(Batches {
+                batches: vec![vec![1, 2], vec![2, 3]],
+                commits: commits.clone(),
+            }),
+            backoff: Backoff::new(BackoffConfig::default()),
+            health_tx,
+            committed_rx,
+        };
+        let (updates_tx, mut updates_rx) = mpsc::channel(1);
+        let fetching = fetcher.spawn(updates_tx);
+
+        let ids = |updates: Vec<CommonUpdate>| updates.iter().map(|u| u.id).collect::<Vec<_>>();
+        assert_eq!(ids(updates_rx.recv().await.unwrap()), [1, 2]);
+        // nothing is fetched or committed while the updates are in flight
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(commits.lock().unwrap().is_empty());
+
+        committed_tx.send_replace(1);
+        committed_tx.send_replace(2);
+        assert_eq!(ids(updates_rx.recv().await.unwrap()), [3]);
+        assert_eq!(*commits.lock().unwrap(), [2]);
+        fetching.abort();
+    }
+}
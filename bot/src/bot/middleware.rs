@@ -0,0 +1,339 @@
+use crate::communicator::Communicate;
+use api::{
+    basic_types::{ChatIntId, UpdateId, UserId},
+    proto::{CommonUpdate, Update, User},
+};
+use async_trait::async_trait;
+use compact_str::CompactString;
+use log::{debug, info};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Whether the update goes on to the next middleware and the modules
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// State of a single update shared by the middlewares handling it
+#[derive(Debug)]
+pub struct UpdateContext {
+    pub update_id: UpdateId,
+    pub received_at: Instant,
+    /// Name of the middleware that stopped the update
+    pub stopped_by: Option<CompactString>,
+    /// Values middlewares pass to each other
+    pub values: HashMap<CompactString, CompactString>,
+}
+
+impl UpdateContext {
+    pub fn new(update_id: UpdateId) -> Self {
+        Self {
+            update_id,
+            received_at: Instant::now(),
+            stopped_by: None,
+            values: Default::default(),
+        }
+    }
+}
+
+/// Runs around every update, `before` hooks in the order the middlewares were added,
+/// `after` hooks in reverse for those whose `before` was run
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Stopping the update keeps it from the rest of the middlewares and the modules,
+    /// a failed middleware is logged and the update goes on
+    async fn before(
+        &self,
+        _comm: &dyn Communicate,
+        _update: &CommonUpdate,
+        _ctx: &mut UpdateContext,
+    ) -> eyre::Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    async fn after(
+        &self,
+        _comm: &dyn Communicate,
+        _update: &CommonUpdate,
+        _ctx: &UpdateContext,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+/// Built-in middlewares, all off by default
+#[derive(Debug, Clone, Default)]
+pub struct FilterConfig {
+    /// Chats the bot works in, any if empty
+    pub allowed_chats: HashSet<ChatIntId>,
+    pub banned_users: HashSet<UserId>,
+    pub ignore_bots: bool,
+    pub ignore_forwarded: bool,
+    /// Logs update counts and handling time every that many updates
+    pub metrics_every: Option<u64>,
+}
+
+impl FilterConfig {
+    pub(crate) fn middlewares(self) -> Vec<Box<dyn Middleware>> {
+        let mut middlewares: Vec<Box<dyn Middleware>> = vec![];
+        if let Some(every) = self.metrics_every {
+            middlewares.push(Box::new(UpdateMetrics::new(every)));
+        }
+        if !self.allowed_chats.is_empty() {
+            middlewares.push(Box::new(ChatAllowlist(self.allowed_chats)));
+        }
+        if !self.banned_users.is_empty() {
+            middlewares.push(Box::new(BannedUsers(self.banned_users)));
+        }
+        if self.ignore_bots {
+            middlewares.push(Box::new(IgnoreBots));
+        }
+        if self.ignore_forwarded {
+            middlewares.push(Box::new(IgnoreForwarded));
+        }
+        middlewares
+    }
+}
+
+/// Updates of other chats are dropped, updates without a chat are let through
+pub struct ChatAllowlist(pub HashSet<ChatIntId>);
+
+#[async_trait]
+impl Middleware for ChatAllowlist {
+    fn name(&self) -> &str {
+        "chat allowlist"
+    }
+
+    async fn before(
+        &self,
+        _comm: &dyn Communicate,
+        update: &CommonUpdate,
+        _ctx: &mut UpdateContext,
+    ) -> eyre::Result<Flow> {
+        Ok(match update_chat_id(&update.data) {
+            Some(chat_id) if !self.0.contains(&chat_id) => Flow::Stop,
+            _ => Flow::Continue,
+        })
+    }
+}
+
+pub struct BannedUsers(pub HashSet<UserId>);
+
+#[async_trait]
+impl Middleware for BannedUsers {
+    fn name(&self) -> &str {
+        "banned users"
+    }
+
+    async fn before(
+        &self,
+        _comm: &dyn Communicate,
+        update: &CommonUpdate,
+        _ctx: &mut UpdateContext,
+    ) -> eyre::Result<Flow> {
+        Ok(match update_sender(&update.data) {
+            Some(user) if self.0.contains(&user.id) => Flow::Stop,
+            _ => Flow::Continue,
+        })
+    }
+}
+
+pub struct IgnoreBots;
+
+#[async_trait]
+impl Middleware for IgnoreBots {
+    fn name(&self) -> &str {
+        "ignore bots"
+    }
+
+    async fn before(
+        &self,
+        _comm: &dyn Communicate,
+        update: &CommonUpdate,
+        _ctx: &mut UpdateContext,
+    ) -> eyre::Result<Flow> {
+        Ok(match update_sender(&update.data) {
+            Some(user) if user.is_bot => Flow::Stop,
+            _ => Flow::Continue,
+        })
+    }
+}
+
+pub struct IgnoreForwarded;
+
+#[async_trait]
+impl Middleware for IgnoreForwarded {
+    fn name(&self) -> &str {
+        "ignore forwarded"
+    }
+
+    async fn before(
+        &self,
+        _comm: &dyn Communicate,
+        update: &CommonUpdate,
+        _ctx: &mut UpdateContext,
+    ) -> eyre::Result<Flow> {
+        let forwarded = match &update.data {
+            Update::MessageUpdate(message) | Update::EditedMessageUpdate(message) => {
+                message.forward_date.is_some()
+            }
+            _ => false,
+        };
+        Ok(if forwarded {
+            Flow::Stop
+        } else {
+            Flow::Continue
+        })
+    }
+}
+
+/// Counts updates and the time spent on them
+pub struct UpdateMetrics {
+    every: u64,
+    handled: AtomicU64,
+    stopped: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+impl UpdateMetrics {
+    pub fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            handled: AtomicU64::new(0),
+            stopped: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for UpdateMetrics {
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    async fn after(
+        &self,
+        _comm: &dyn Communicate,
+        _update: &CommonUpdate,
+        ctx: &UpdateContext,
+    ) -> eyre::Result<()> {
+        let elapsed = ctx.received_at.elapsed();
+        debug!("update #{} took {elapsed:?}", ctx.update_id);
+        if ctx.stopped_by.is_some() {
+            self.stopped.fetch_add(1, Ordering::Relaxed);
+        }
+        let total_micros = self
+            .total_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed)
+            + elapsed.as_micros() as u64;
+        let handled = self.handled.fetch_add(1, Ordering::Relaxed) + 1;
+        if handled.is_multiple_of(self.every) {
+            info!(
+                "{handled} updates, {} filtered out, {:?} per update on average",
+                self.stopped.load(Ordering::Relaxed),
+                Duration::from_micros(total_micros / handled)
+            );
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn update_chat_id(update: &Update) -> Option<ChatIntId> {
+    match update {
+        Update::MessageUpdate(msg)
+        | Update::EditedMessageUpdate(msg)
+        | Update::ChannelPostUpdate(msg)
+        | Update::EditedChannelPostUpdate(msg) => Some(msg.chat.id),
+        Update::CallbackQueryUpdate(query) => query.message.as_ref().map(|m| m.chat.id),
+        Update::MyChatMemberUpdate(member) | Update::ChatMemberUpdate(member) => {
+            Some(member.chat.id)
+        }
+        Update::ChatJoinRequestUpdate(request) => Some(request.chat.id),
+        _ => None,
+    }
+}
+
+/// User the update comes from, none for channel posts and updates without a user
+pub fn update_sender(update: &Update) -> Option<&User> {
+    match update {
+        Update::MessageUpdate(msg) | Update::EditedMessageUpdate(msg) => msg.from.as_ref(),
+        Update::CallbackQueryUpdate(query) => Some(&query.from),
+        Update::InlineQueryUpdate(query) => Some(&query.from),
+        Update::MyChatMemberUpdate(member) | Update::ChatMemberUpdate(member) => Some(&member.from),
+        Update::ChatJoinRequestUpdate(request) => Some(&request.from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communicator::Communicator;
+    use api::proto::{Chat, Message};
+
+    fn message_update(chat_id: ChatIntId, from: User, forwarded: bool) -> CommonUpdate {
+        CommonUpdate {
+            id: 1,
+            data: Update::MessageUpdate(Message {
+                chat: Chat {
+                    id: chat_id,
+                    ..Default::default()
+                },
+                from: Some(from),
+                forward_date: forwarded.then_some(0),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn user(id: UserId, is_bot: bool) -> User {
+        User {
+            id,
+            is_bot,
+            first_name: "user".into(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: None,
+            added_to_attachment_menu: None,
+            can_join_groups: None,
+            can_read_all_group_messages: None,
+            supports_inline_queries: None,
+        }
+    }
+
+    async fn flows(middlewares: &[Box<dyn Middleware>], update: &CommonUpdate) -> Vec<Flow> {
+        let comm = Communicator::new("token");
+        let mut flows = vec![];
+        for middleware in middlewares {
+            let mut ctx = UpdateContext::new(update.id);
+            flows.push(middleware.before(&comm, update, &mut ctx).await.unwrap());
+        }
+        flows
+    }
+
+    #[tokio::test]
+    async fn filters_stop_matching_updates() {
+        let middlewares = FilterConfig {
+            allowed_chats: HashSet::from([10]),
+            banned_users: HashSet::from([666]),
+            ignore_bots: true,
+            ignore_forwarded: true,
+            metrics_every: None,
+        }
+        .middlewares();
+        use Flow::*;
+        let update = message_update(10, user(1, false), false);
+        assert_eq!(flows(&middlewares, &update).await, [Continue; 4]);
+        let update = message_update(20, user(666, true), true);
+        assert_eq!(flows(&middlewares, &update).await, [Stop; 4]);
+    }
+}
@@ -1,44 +1,94 @@
 use crate::{
-    bot::{command::BotCommandInfo, config::BotConfig},
-    communicator::{Communicate, Communicator},
+    bot::{
+        chat_settings::{ChatSettings, ChatSettingsStore},
+        command::BotIdentity,
+        config::BotConfig,
+        dispatcher::{jab_commands, Dispatcher, Done, Handler, JAB_MODULE_NAME},
+        event::{Event, EventSender},
+        fetcher::Fetcher,
+        middleware::Middleware,
+        offset::OffsetWriter,
+        registry::CommandRegistry,
+        supervisor::{SupervisedModule, SupervisorConfig},
+        throttle::Throttle,
+    },
+    communicator::{Communicate, Communicator, DryRunCommunicator, SharedCommunicator},
     connector::{
+        health::{Backoff, ConnectorHealth},
         polling::{PollingConnector, PollingConnectorConfig},
+        replay::ReplayConnector,
         webhook::{WebhookConnector, WebhookConnectorConfig},
         Connector, ConnectorMode,
     },
     module::PersistentModule,
-    persistence::Persistence,
+    persistence::{seal, unseal, write_atomically, Persistence},
+    storage::{
+        file::FileStorage, sqlite::SqliteStorage, ChatStorage, SharedStorage, StorageBackend,
+    },
 };
 use api::{
-    basic_types::UpdateId,
-    proto::{Message, Update},
+    basic_types::{UpdateId, UserId},
+    proto::{CommonUpdate, Message},
 };
 use bincode::{Decode, Encode};
-use compact_str::{CompactString, ToCompactString};
-use eyre::bail;
-use futures_util::future::try_join_all;
+use compact_str::CompactString;
+use eyre::{bail, WrapErr};
 use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    io::{Read, Write},
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
-    str::FromStr,
+    sync::{Arc, Mutex},
     time::Duration,
 };
-use tokio::sync::mpsc::{error::TryRecvError, Receiver};
+use tokio::sync::{
+    mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender},
+    watch,
+};
 
+pub mod args;
+pub mod chat_settings;
 pub mod command;
 pub mod config;
+mod dispatcher;
+pub mod event;
+mod fetcher;
+pub mod middleware;
+mod offset;
+pub mod registry;
+pub mod supervisor;
+pub mod throttle;
 
 pub struct Bot {
     last_update_id: UpdateId,
-    connector: Box<dyn Connector>,
-    communicator: Communicator,
-    modules: HashMap<CompactString, BinPersistentModule>,
+    fetcher: Option<Fetcher>,
+    health_rx: watch::Receiver<ConnectorHealth>,
+    committed_tx: watch::Sender<UpdateId>,
+    communicator: SharedCommunicator,
+    modules: HashMap<CompactString, SharedModule>,
+    commands: CommandRegistry,
+    chat_settings: Arc<ChatSettingsStore>,
+    throttle: Arc<Throttle>,
+    middlewares: Vec<Box<dyn Middleware>>,
+    owners: HashSet<UserId>,
+    supervisor: SupervisorConfig,
     work_dir: PathBuf,
     state_rx: Receiver<State>,
+    events_tx: UnboundedSender<Event>,
+    events_rx: UnboundedReceiver<Event>,
     skip_missed_updates: bool,
     data_file_name: CompactString,
+    autosave_interval: Option<Duration>,
+    storage: SharedStorage,
+    /// Data that failed to load, it starts anew
+    quarantined: HashSet<CompactString>,
+    /// Data that failed to load and is kept in the storage as it was instead of being saved
+    unsaved: HashSet<CompactString>,
+    /// Whether the updates come from a journal, they are older than the last one handled then,
+    /// and the data is left as it was
+    replaying: bool,
+    /// Running while the bot is
+    offset_writer: Option<OffsetWriter>,
 }
 
 #[derive(Debug)]
@@ -46,45 +96,116 @@ pub enum State {
     Shutdown,
 }
 
-type BinPersistentModule = Box<dyn PersistentModule<Input = Vec<u8>, Output = Vec<u8>>>;
+type SharedModule = Arc<dyn PersistentModule<Input = Vec<u8>, Output = Vec<u8>>>;
+
+/// How long in-flight updates may take to finish on shutdown
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 impl Bot {
-    pub fn with_config(token: &str, state_rx: Receiver<State>, config: BotConfig) -> Self {
-        let connector: Box<dyn Connector> = match config.connector_mode {
-            ConnectorMode::Polling => {
+    pub fn with_config(
+        token: &str,
+        state_rx: Receiver<State>,
+        config: BotConfig,
+    ) -> eyre::Result<Self> {
+        config.connector.verify()?;
+        let connector_config = config.connector;
+        let connector: Box<dyn Connector> = match (&config.journal, connector_config.mode) {
+            (Some(journal), _) => Box::new(ReplayConnector::open(journal)?),
+            (None, ConnectorMode::Polling) => {
                 let connector_config = PollingConnectorConfig {
-                    allowed_updates: config.allowed_updates.into_iter().collect(),
-                    limit: config.update_limit,
-                    timeout: config.polling_timeout,
+                    allowed_updates: connector_config.allowed_updates.into_iter().collect(),
+                    limit: connector_config.update_limit,
+                    timeout: connector_config.timeout,
                 };
                 Box::new(PollingConnector::with_config(token, connector_config))
             }
-            ConnectorMode::Webhook => {
+            (None, ConnectorMode::Webhook) => {
+                let webhook = connector_config.webhook;
                 let connector_config = WebhookConnectorConfig {
-                    https_url: std::env::var("WEBHOOK_HTTPS_URL")
-                        .unwrap()
-                        .to_compact_string(),
-                    ip_address: std::env::var("WEBHOOK_IP_V4_ADDR")
-                        .map(|s| s.to_compact_string())
-                        .ok(),
-                    drop_pending_updates: config.skip_missed_updates,
-                    allowed_updates: config.allowed_updates.into_iter().collect(),
+                    https_url: webhook.https_url,
+                    ip_address: webhook.ip_address,
+                    drop_pending_updates: connector_config.skip_missed_updates,
+                    allowed_updates: connector_config.allowed_updates.into_iter().collect(),
+                    listen_address: webhook.listen_address,
+                    port: webhook.port,
+                    tls: webhook.tls,
+                    upload_certificate: webhook.upload_certificate,
+                    cert_dir: config.work_dir.join("self_signed_certs"),
+                    queue_path: config
+                        .work_dir
+                        .join(format!("{}.queue", config.data_file_name)),
+                    queue_size: webhook.queue_size,
+                    batch_limit: connector_config.update_limit.unwrap_or(100) as usize,
                     ..Default::default()
                 };
                 Box::new(WebhookConnector::with_config(token, connector_config))
             }
         };
 
-        Self {
-            connector,
-            communicator: Communicator::new(token),
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (health_tx, health_rx) = watch::channel(ConnectorHealth::Starting);
+        let (committed_tx, committed_rx) = watch::channel(0);
+        let mut commands = CommandRegistry::default();
+        commands.register(JAB_MODULE_NAME, jab_commands());
+        let data_path = config.work_dir.join(Path::new(&config.data_file_name));
+        let read_only = config.read_only || config.journal.is_some();
+        let storage: SharedStorage = match (config.storage, read_only) {
+            (StorageBackend::File, false) => Arc::new(Mutex::new(
+                FileStorage::open(&data_path, config.backups)
+                    .wrap_err_with(|| format!("failed to open {data_path:?}"))?,
+            )),
+            (StorageBackend::File, true) => Arc::new(Mutex::new(
+                FileStorage::open_read_only(&data_path)
+                    .wrap_err_with(|| format!("failed to open {data_path:?}"))?,
+            )),
+            (StorageBackend::Sqlite, false) => Arc::new(Mutex::new(
+                SqliteStorage::open(&data_path)
+                    .wrap_err_with(|| format!("failed to open {data_path:?}"))?,
+            )),
+            (StorageBackend::Sqlite, true) => Arc::new(Mutex::new(
+                SqliteStorage::open_read_only(&data_path)
+                    .wrap_err_with(|| format!("failed to open {data_path:?}"))?,
+            )),
+        };
+
+        Ok(Self {
+            fetcher: Some(Fetcher {
+                connector,
+                backoff: Backoff::new(config.backoff),
+                health_tx,
+                committed_rx,
+            }),
+            health_rx,
+            committed_tx,
+            communicator: if config.dry_run {
+                Arc::new(DryRunCommunicator::new(token))
+            } else {
+                Arc::new(Communicator::new(token))
+            },
             last_update_id: 0,
             modules: Default::default(),
+            commands,
+            chat_settings: Arc::new(ChatSettingsStore::new(ChatSettings {
+                triggers: config.triggers,
+                ..Default::default()
+            })),
+            throttle: Default::default(),
+            middlewares: config.filters.middlewares(),
+            owners: config.owners,
+            supervisor: config.supervisor,
             work_dir: config.work_dir,
             state_rx,
-            skip_missed_updates: config.skip_missed_updates,
+            events_tx,
+            events_rx,
+            skip_missed_updates: connector_config.skip_missed_updates,
             data_file_name: config.data_file_name,
-        }
+            autosave_interval: config.autosave_interval,
+            storage,
+            quarantined: Default::default(),
+            unsaved: Default::default(),
+            replaying: config.journal.is_some(),
+            offset_writer: None,
+        })
     }
 
     /// Each module has to have a unique name
@@ -93,224 +214,632 @@ impl Bot {
         name: &str,
         module: impl PersistentModule<Output = Vec<u8>, Input = Vec<u8>> + 'static,
     ) {
-        if self.modules.contains_key(name) {
+        // the bot data is kept under names starting with "jab." as well
+        if self.modules.contains_key(name) || name.split('.').next() == Some(JAB_MODULE_NAME) {
             error!("failed to insert '{name}' as the module with that name is present already");
         } else {
-            self.modules.insert(name.into(), Box::new(module));
+            self.commands.register(name, module.commands());
+            self.modules.insert(name.into(), Arc::new(module));
+        }
+    }
+
+    /// Runs after the built-in filters, in the order added
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middlewares.push(Box::new(middleware));
+    }
+
+    /// Marks the update as handled, it will not be fetched again even after a restart
+    fn commit_update(&mut self, id: UpdateId) {
+        if id > self.last_update_id {
+            self.last_update_id = id;
+            if let Some(writer) = &self.offset_writer {
+                writer.write(id);
+            }
         }
+        self.committed_tx.send_replace(id);
+    }
+
+    fn offset_file_path(&self) -> PathBuf {
+        self.work_dir
+            .join(format!("{}.offset", self.data_file_name))
     }
 
-    async fn handle_message_update(&mut self, message: Message) -> eyre::Result<()> {
-        let Ok(cmd) = BotCommandInfo::try_from(&message) else {
+    /// The offset is persisted on every commit, unlike the rest of the data
+    fn load_offset(&mut self) -> eyre::Result<()> {
+        let path = self.offset_file_path();
+        if !path.exists() {
             return Ok(());
-        };
+        }
+        let offset = std::fs::read_to_string(&path)?.trim().parse::<UpdateId>()?;
+        if offset > self.last_update_id {
+            self.last_update_id = offset;
+        }
+        Ok(())
+    }
 
-        match JabCommandName::from_str(cmd.name()) {
-            Ok(name) => {
-                match name {
-                    JabCommandName::Del => {
-                        self.communicator.del(&message).await?;
-                    }
-                };
-                return Ok(());
+    pub fn comm(&self) -> &dyn Communicate {
+        self.communicator.as_ref()
+    }
+
+    /// Reflects whether updates are being fetched successfully
+    pub fn health(&self) -> watch::Receiver<ConnectorHealth> {
+        self.health_rx.clone()
+    }
+
+    /// Modules may use it to produce events handled in the bot main loop
+    pub fn event_sender(&self) -> EventSender {
+        EventSender::new(self.events_tx.clone())
+    }
+
+    /// Unknown identity makes the bot accept commands addressed to other bots
+    async fn identify(&self) -> BotIdentity {
+        let me = match self.communicator.get_me().await {
+            Ok(response) => response.into_result().map_err(eyre::Report::from),
+            Err(err) => Err(err),
+        };
+        match me {
+            Ok(user) => {
+                info!(
+                    "running as @{}",
+                    user.username.as_deref().unwrap_or_default()
+                );
+                BotIdentity {
+                    id: user.id,
+                    username: user.username.unwrap_or_default(),
+                }
             }
             Err(err) => {
-                debug!("{err}");
+                error!("failed to get the bot account, {err}");
+                BotIdentity::default()
+            }
+        }
+    }
+
+    /// Data that fails to load is only set aside to a side file if `set_aside`,
+    /// it is left out of the loaded data either way
+    fn load_data(&mut self, set_aside: bool) -> eyre::Result<()> {
+        let (mut entries, last_update_id) = {
+            let storage = self.storage.lock().expect("storage lock poisoned");
+            let mut entries = BTreeMap::new();
+            for name in storage.keys(MODULES_NAMESPACE)? {
+                if let Some(data) = storage.get(MODULES_NAMESPACE, &name)? {
+                    entries.insert(name, Some(data));
+                }
             }
+            for key in [CHAT_SETTINGS_KEY, THROTTLE_KEY] {
+                if let Some(data) = storage.get(BOT_NAMESPACE, key)? {
+                    entries.insert(bot_entry_name(key), Some(data));
+                }
+            }
+            (entries, storage.get(BOT_NAMESPACE, LAST_UPDATE_ID_KEY)?)
         };
 
-        try_join_all(
-            self.modules
-                .values_mut()
-                .map(|m| m.try_execute_command(&self.communicator, &cmd, &message)),
-        )
-        .await?;
+        // the offset has its own file now, the storage only has it if saved before
+        if let Some(last_update_id) = last_update_id {
+            match bincode::decode_from_slice(&last_update_id, bincode::config::standard()) {
+                Ok((last_update_id, _)) => self.last_update_id = last_update_id,
+                Err(err) => error!("failed to load the last update id, {err}"),
+            }
+        }
+
+        // the bot data was kept as that of a module named "jab" before
+        if let Some(Some(data)) = entries.remove(JAB_MODULE_NAME) {
+            let jab = unseal(data.clone(), &[]).and_then(|jab| {
+                Ok(bincode::decode_from_slice::<JabData, _>(&jab, bincode::config::standard())?.0)
+            });
+            match jab {
+                Ok(jab) => {
+                    for (key, data) in [
+                        (CHAT_SETTINGS_KEY, jab.chat_settings),
+                        (THROTTLE_KEY, jab.throttle),
+                    ] {
+                        entries.entry(bot_entry_name(key)).or_insert(Some(data));
+                    }
+                }
+                Err(err) if set_aside => {
+                    error!("failed to load '{JAB_MODULE_NAME}' data, setting it aside, {err}");
+                    self.quarantine(JAB_MODULE_NAME, Some(&data));
+                }
+                Err(err) => {
+                    error!("failed to load '{JAB_MODULE_NAME}' data, {err}");
+                    self.quarantined.insert(JAB_MODULE_NAME.into());
+                }
+            }
+        }
+        // chats of a module may be saved even if its own data is not
+        for name in self.modules.keys() {
+            entries.entry(name.to_string()).or_insert(None);
+        }
+
+        // data that fails to load must not keep the rest from loading
+        let mut loaded = HashSet::new();
+        for (name, data) in entries {
+            match self.load_entry(&name, data.clone()) {
+                Ok(true) => {
+                    if data.is_some() {
+                        loaded.insert(name);
+                    }
+                }
+                Ok(false) => {
+                    warn!("loaded '{name}' data, but the module itself is not present")
+                }
+                Err(err) if set_aside => {
+                    error!("failed to load '{name}' data, setting it aside, {err}");
+                    self.quarantine(&name, data.as_deref());
+                }
+                Err(err) => {
+                    error!("failed to load '{name}' data, {err}");
+                    self.quarantined.insert(name.into());
+                }
+            }
+        }
+
+        let mut empty = self
+            .modules
+            .keys()
+            .filter(|name| !loaded.contains(name.as_str()))
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        if !empty.is_empty() {
+            empty.sort_unstable();
+            warn!("modules starting with no data: {}", empty.join(", "));
+        }
 
         Ok(())
     }
 
-    fn check_is_old_update(&mut self, id: UpdateId) -> bool {
-        if self.last_update_id >= id {
-            true
-        } else if self.last_update_id != 0 && self.last_update_id < id - 1 {
-            error!(
-                "some updates skipped! last update id = {}, new update id = {}",
-                self.last_update_id, id
-            );
-            self.last_update_id = id;
-            false
+    /// Loads the data of the bot or a module with its chats,
+    /// returns whether there is such a module
+    fn load_entry(&mut self, name: &str, data: Option<Vec<u8>>) -> eyre::Result<bool> {
+        let mut storage = self.storage.lock().expect("storage lock poisoned");
+        let chats = ChatStorage::new(&mut *storage, name);
+        if name == bot_entry_name(CHAT_SETTINGS_KEY) {
+            let Some(chat_settings) = Arc::get_mut(&mut self.chat_settings) else {
+                bail!("bot data is in use, cannot load it");
+            };
+            load_or_reset(chat_settings, data, &chats)?;
+        } else if name == bot_entry_name(THROTTLE_KEY) {
+            let Some(throttle) = Arc::get_mut(&mut self.throttle) else {
+                bail!("bot data is in use, cannot load it");
+            };
+            load_or_reset(throttle, data, &chats)?;
+        } else if let Some(module) = self.modules.get_mut(name) {
+            let Some(module) = Arc::get_mut(module) else {
+                bail!("'{name}' module is in use, cannot load its data");
+            };
+            load_or_reset(module, data, &chats)?;
         } else {
-            self.last_update_id = id;
-            false
+            return Ok(false);
         }
+        Ok(true)
     }
 
-    pub fn comm(&self) -> &dyn Communicate {
-        &self.communicator
+    /// Copies the data that failed to load to a side file, so that the data it starts with
+    /// anew is saved in its place; the data is kept in the storage untouched
+    /// if it cannot be copied or has chats, which are not copied
+    fn quarantine(&mut self, name: &str, data: Option<&[u8]>) {
+        let path = self
+            .work_dir
+            .join(format!("{}.{name}.quarantine", self.data_file_name));
+        let set_aside = match data.map(|data| write_atomically(&path, data)) {
+            Some(Ok(())) => {
+                warn!("'{name}' data is set aside to {path:?}");
+                true
+            }
+            Some(Err(err)) => {
+                error!("failed to set '{name}' data aside, path = {path:?}, {err}");
+                false
+            }
+            None => true,
+        };
+        let mut storage = self.storage.lock().expect("storage lock poisoned");
+        let has_chats = ChatStorage::new(&mut *storage, name)
+            .chats()
+            .map_or(true, |chats| !chats.is_empty());
+        if !set_aside || has_chats {
+            warn!("'{name}' data is kept as it was, the new one is not saved");
+            self.unsaved.insert(name.into());
+        }
+        self.quarantined.insert(name.into());
     }
 
-    fn load_data(&mut self) -> eyre::Result<()> {
-        let path = self.work_dir.join(Path::new(&self.data_file_name));
-        let mut file = std::fs::File::options().read(true).open(path.as_path())?;
-        let metadata = std::fs::metadata(path)?;
-        let mut buffer = vec![0; metadata.len() as usize];
-        file.read_exact(&mut buffer)?;
-        self.deserialize(buffer)?;
-        Ok(())
+    fn save_data(&self) -> eyre::Result<()> {
+        // the live bot is not to see the data a replay leaves
+        if self.replaying {
+            debug!("replaying a journal, bot data is not saved");
+            return Ok(());
+        }
+        let mut modules = vec![];
+        for (name, module) in &self.modules {
+            modules.push((
+                name.to_string(),
+                seal(module.migrations().len(), module.serialize()?)?,
+            ));
+        }
+        let bot_data = [
+            (
+                CHAT_SETTINGS_KEY,
+                seal(
+                    self.chat_settings.migrations().len(),
+                    self.chat_settings.serialize()?,
+                )?,
+            ),
+            (
+                THROTTLE_KEY,
+                seal(self.throttle.migrations().len(), self.throttle.serialize()?)?,
+            ),
+        ];
+
+        let mut storage = self.storage.lock().expect("storage lock poisoned");
+        // a module's data and its chats are saved together or not at all
+        storage.transaction(&mut |storage| {
+            // unchanged data is not written again by the storage
+            for (key, data) in &bot_data {
+                if !self.unsaved.contains(bot_entry_name(key).as_str()) {
+                    storage.put(BOT_NAMESPACE, key, data.clone())?;
+                }
+            }
+            if !self.unsaved.contains(JAB_MODULE_NAME) {
+                storage.delete(MODULES_NAMESPACE, JAB_MODULE_NAME)?;
+            }
+            for (name, data) in &modules {
+                if !self.unsaved.contains(name.as_str()) {
+                    storage.put(MODULES_NAMESPACE, name, data.clone())?;
+                }
+            }
+            for (name, module) in &self.modules {
+                if !self.unsaved.contains(name) {
+                    module.save_chats(&mut ChatStorage::new(storage, name))?;
+                }
+            }
+            if storage.get(BOT_NAMESPACE, LAST_UPDATE_ID_KEY)?.is_some() {
+                self.write_offset()?;
+                storage.delete(BOT_NAMESPACE, LAST_UPDATE_ID_KEY)?;
+            }
+            Ok(())
+        })?;
+        storage.flush()
     }
 
-    fn save_data(&self) -> eyre::Result<()> {
-        let data = self.serialize()?;
-        let path = self.work_dir.join(Path::new(&self.data_file_name));
-        let mut file = std::fs::File::options()
-            .write(true)
-            .create(true)
-            .open(path)?;
-        file.write_all(data.as_slice())?;
-        Ok(())
+    /// Writes the offset out of turn, through the writer while it is running
+    fn write_offset(&self) -> std::io::Result<()> {
+        match &self.offset_writer {
+            Some(writer) => {
+                writer.write(self.last_update_id);
+                Ok(())
+            }
+            None => write_atomically(
+                &self.offset_file_path(),
+                self.last_update_id.to_string().as_bytes(),
+            ),
+        }
+    }
+
+    /// All the data as JSON, module data by module name;
+    /// the data that fails to load is left as it is and listed in the export instead
+    pub fn export_data(&mut self) -> eyre::Result<serde_json::Value> {
+        self.load_data(false)?;
+        self.load_offset()?;
+        let exported = |name: &str| !self.quarantined.contains(name);
+        // the bot data was kept as a whole before
+        let bot_exported =
+            |key: &str| exported(JAB_MODULE_NAME) && exported(bot_entry_name(key).as_str());
+        let mut modules = BTreeMap::new();
+        for (name, module) in &self.modules {
+            if exported(name) {
+                modules.insert(name.to_string(), module.export()?);
+            }
+        }
+        let mut quarantined = self
+            .quarantined
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        quarantined.sort_unstable();
+        if !quarantined.is_empty() {
+            warn!(
+                "data that failed to load is not exported: {}",
+                quarantined.join(", ")
+            );
+        }
+        Ok(serde_json::to_value(ExportedData {
+            last_update_id: self.last_update_id,
+            chat_settings: bot_exported(CHAT_SETTINGS_KEY)
+                .then(|| self.chat_settings.export())
+                .transpose()?,
+            throttle: bot_exported(THROTTLE_KEY)
+                .then(|| self.throttle.export())
+                .transpose()?,
+            modules,
+            quarantined,
+        })?)
+    }
+
+    /// Replaces the data with the exported one and saves it,
+    /// the data missing from the export is kept as it is
+    pub fn import_data(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        self.load_data(true)?;
+        let data = serde_json::from_value::<ExportedData>(value)?;
+        if !data.quarantined.is_empty() {
+            warn!(
+                "data that failed to load on export is kept as it is: {}",
+                data.quarantined.join(", ")
+            );
+        }
+        let (Some(chat_settings), Some(throttle)) = (
+            Arc::get_mut(&mut self.chat_settings),
+            Arc::get_mut(&mut self.throttle),
+        ) else {
+            bail!("bot data is in use, cannot import it");
+        };
+        let mut imported = vec![];
+        if let Some(value) = data.chat_settings {
+            chat_settings.import(value)?;
+            imported.push(bot_entry_name(CHAT_SETTINGS_KEY));
+        }
+        if let Some(value) = data.throttle {
+            throttle.import(value)?;
+            imported.push(bot_entry_name(THROTTLE_KEY));
+        }
+        if imported.len() == 2 {
+            imported.push(JAB_MODULE_NAME.into());
+        }
+
+        for (name, value) in data.modules {
+            let Some(module) = self.modules.get_mut(name.as_str()) else {
+                warn!("skipping '{name}' data, the module itself is not present");
+                continue;
+            };
+            let Some(module) = Arc::get_mut(module) else {
+                bail!("'{name}' module is in use, cannot import its data");
+            };
+            module
+                .import(value)
+                .map_err(|err| err.wrap_err(format!("failed to import '{name}' data")))?;
+            imported.push(name);
+        }
+        for name in imported {
+            self.quarantined.remove(name.as_str());
+            self.unsaved.remove(name.as_str());
+        }
+        self.last_update_id = data.last_update_id;
+        self.write_offset()?;
+        self.save_data()
     }
 
     pub async fn start(mut self) {
-        self.load_data().unwrap_or_else(|err| {
+        self.load_data(!self.replaying).unwrap_or_else(|err| {
             error!(
                 "failed to load bot data, path = {:?}, {}",
                 self.work_dir, err
             )
         });
+        self.load_offset().unwrap_or_else(|err| {
+            error!(
+                "failed to load update offset, path = {:?}, {}",
+                self.offset_file_path(),
+                err
+            )
+        });
+        // the journal is replayed from the start, the offset only ever grows anyway
+        let first_update_id = if self.replaying {
+            0
+        } else {
+            self.last_update_id
+        };
+        self.committed_tx.send_replace(first_update_id);
+        if !self.replaying {
+            self.offset_writer = Some(OffsetWriter::spawn(
+                self.offset_file_path(),
+                self.last_update_id,
+            ));
+        }
 
-        self.connector
+        let mut fetcher = self.fetcher.take().expect("bot started twice");
+        fetcher
+            .connector
             .on_startup()
             .await
             .expect("connector failed on startup");
 
-        let mut interval = tokio::time::interval(Duration::from_millis(1000));
-
-        loop {
-            match self.state_rx.try_recv() {
-                Err(TryRecvError::Disconnected) => {
-                    panic!("bot signal channel died");
-                }
-                Ok(State::Shutdown) => {
-                    info!("shutdown signal received, saving bot data..");
-                    if let Err(err) = self.save_data() {
-                        error!("failed to save bot data, {err}");
-                    }
-                    return;
-                }
-                _ => {}
-            };
+        let (updates_tx, mut updates_rx) = mpsc::channel(1);
+        let fetching = fetcher.spawn(updates_tx);
 
-            let updates = match self.connector.fetch_updates().await {
-                Ok(updates) => updates,
-                Err(err) => {
-                    error!("{err}");
-                    continue;
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let me = self.identify().await;
+        let handler = Arc::new(Handler {
+            communicator: self.communicator.clone(),
+            me,
+            modules: self
+                .modules
+                .iter()
+                .map(|(name, module)| {
+                    SupervisedModule::new(name.clone(), module.clone(), self.supervisor.clone())
+                })
+                .collect(),
+            commands: std::mem::take(&mut self.commands),
+            chat_settings: self.chat_settings.clone(),
+            throttle: self.throttle.clone(),
+            middlewares: std::mem::take(&mut self.middlewares),
+            owners: self.owners.clone(),
+            reply_on_error: self.supervisor.reply_on_error,
+        });
+        match self
+            .communicator
+            .set_my_commands(handler.commands.bot_commands())
+            .await
+        {
+            Ok(response) => {
+                if let Err(err) = response.into_result() {
+                    error!("failed to set bot commands, {err}");
                 }
-            };
-
-            if updates.is_empty() {
-                continue;
-            }
-
-            if self.last_update_id == 0 && self.skip_missed_updates {
-                self.last_update_id = updates.into_iter().map(|u| u.id).max().unwrap();
-                continue;
             }
+            Err(err) => error!("failed to set bot commands, {err}"),
+        }
+        handler.on_startup().await;
+        let mut dispatcher = Dispatcher::new(handler.clone(), done_tx, first_update_id);
 
-            for update in updates {
-                if self.check_is_old_update(update.id) {
-                    continue;
+        // the first tick completes at once, the data was just loaded;
+        // the interval is not polled with autosave off
+        let mut autosave =
+            tokio::time::interval(self.autosave_interval.unwrap_or(Duration::from_secs(3600)));
+        autosave.tick().await;
+        loop {
+            tokio::select! {
+                biased;
+                state = self.state_rx.recv() => {
+                    match state {
+                        Some(State::Shutdown) => {
+                            info!("shutdown signal received, saving bot data..");
+                        }
+                        None => {
+                            warn!("bot signal channel died, saving bot data..");
+                        }
+                    };
+                    break;
+                }
+                Some(done) = done_rx.recv() => {
+                    let handled_up_to = dispatcher.on_done(done);
+                    self.commit_update(handled_up_to);
                 }
-                match &update.data {
-                    Update::MessageUpdate(msg)
-                    | Update::EditedMessageUpdate(msg)
-                    | Update::ChannelPostUpdate(msg)
-                    | Update::EditedChannelPostUpdate(msg) => {
-                        debug!(
-                            "update #{} message received: {}",
-                            update.id,
-                            message_to_string(msg)
-                        );
+                _ = autosave.tick(), if self.autosave_interval.is_some() => {
+                    match self.save_data() {
+                        Ok(()) => debug!("bot data saved"),
+                        Err(err) => error!("failed to autosave bot data, {err}"),
                     }
-                    _ => {
-                        debug!("update received: {update:?}");
+                }
+                Some(event) = self.events_rx.recv() => {
+                    if let Err(err) = self.handle_event(event).await {
+                        error!("{err}");
                     }
                 }
-                match update.data {
-                    Update::MessageUpdate(message) => {
-                        if let Err(report) = self.handle_message_update(message).await {
-                            error!("{}", report);
+                updates = updates_rx.recv() => {
+                    let Some(updates) = updates else {
+                        if *self.health_rx.borrow() == ConnectorHealth::Exhausted {
+                            info!("all updates handled, saving bot data..");
+                        } else {
+                            error!("connector stopped fetching updates");
                         }
-                    }
-                    _ => {}
-                };
+                        break;
+                    };
+                    self.handle_updates(&mut dispatcher, updates);
+                }
             }
+        }
 
-            interval.tick().await;
+        fetching.abort();
+        self.wait_in_flight(&mut dispatcher, &mut done_rx).await;
+        if let Some(writer) = self.offset_writer.take() {
+            writer.finish().await;
+        }
+        handler.on_shutdown().await;
+        if let Err(err) = self.save_data() {
+            error!("failed to save bot data, {err}");
         }
     }
-}
 
-enum JabCommandName {
-    Del,
-}
+    async fn wait_in_flight(
+        &mut self,
+        dispatcher: &mut Dispatcher,
+        done_rx: &mut UnboundedReceiver<Done>,
+    ) {
+        let waiting = async {
+            while !dispatcher.is_idle() {
+                let Some(done) = done_rx.recv().await else {
+                    break;
+                };
+                let handled_up_to = dispatcher.on_done(done);
+                self.commit_update(handled_up_to);
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, waiting)
+            .await
+            .is_err()
+        {
+            warn!("updates still in progress are left uncommitted, they will be fetched again");
+        }
+    }
+
+    async fn handle_event(&mut self, event: Event) -> eyre::Result<()> {
+        debug!("event received: {event:?}");
+        match event {
+            Event::SendMessage { chat_id, text } => {
+                self.communicator
+                    .send_message(&text, chat_id.into())
+                    .await?
+                    .into_result()?;
+            }
+            Event::SaveData => {
+                self.save_data()?;
+            }
+        }
+        Ok(())
+    }
 
-impl FromStr for JabCommandName {
-    type Err = eyre::Report;
+    fn handle_updates(&mut self, dispatcher: &mut Dispatcher, updates: Vec<CommonUpdate>) {
+        if self.last_update_id == 0 && self.skip_missed_updates && !self.replaying {
+            let last_id = updates.into_iter().map(|u| u.id).max().unwrap_or(0);
+            self.commit_update(last_id);
+            return;
+        }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "del" => Ok(JabCommandName::Del),
-            _ => {
-                bail!("jab failed to recognize '{s}' as a possible command");
+        for update in updates {
+            if !dispatcher.is_old_update(update.id) {
+                dispatcher.dispatch(update);
             }
         }
+        self.commit_update(dispatcher.handled_up_to());
     }
 }
 
+/// Data of the bot itself, kept as that of a module named "jab" before it had its own keys
 #[derive(Encode, Decode)]
-struct PersistenceData {
-    modules: HashMap<String, Vec<u8>>,
-    last_update_id: UpdateId,
+struct JabData {
+    chat_settings: Vec<u8>,
+    throttle: Vec<u8>,
 }
 
-impl Persistence for Bot {
-    type Input = Vec<u8>;
-    type Output = Vec<u8>;
-
-    fn serialize(&self) -> eyre::Result<Self::Output> {
-        let mut modules = HashMap::new();
-        for (name, module) in &self.modules {
-            modules.insert(name.to_string(), module.serialize()?);
-        }
-
-        let data = PersistenceData {
-            modules,
-            last_update_id: self.last_update_id,
-        };
-
-        Ok(bincode::encode_to_vec(data, bincode::config::standard())?)
-    }
+#[derive(Serialize, Deserialize)]
+struct ExportedData {
+    last_update_id: UpdateId,
+    chat_settings: Option<serde_json::Value>,
+    throttle: Option<serde_json::Value>,
+    modules: BTreeMap<String, serde_json::Value>,
+    /// Data that failed to load, missing from the export
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    quarantined: Vec<String>,
+}
 
-    fn deserialize(&mut self, input: Self::Input) -> eyre::Result<()> {
-        let data = bincode::decode_from_slice::<PersistenceData, _>(
-            input.as_slice(),
-            bincode::config::standard(),
-        )?
-        .0;
+/// Namespace of the module data by module name
+const MODULES_NAMESPACE: &str = "modules";
+const BOT_NAMESPACE: &str = "bot";
+const LAST_UPDATE_ID_KEY: &str = "last_update_id";
+const CHAT_SETTINGS_KEY: &str = "chat_settings";
+const THROTTLE_KEY: &str = "throttle";
 
-        self.last_update_id = data.last_update_id;
+/// `jab.throttle` for the bot data kept under the `throttle` key
+fn bot_entry_name(key: &str) -> String {
+    format!("{JAB_MODULE_NAME}.{key}")
+}
 
-        for (input_name, input_data) in data.modules {
-            if let Some(module) = self.modules.get_mut(input_name.as_str()) {
-                module.deserialize(input_data)?;
-            } else {
-                warn!("loaded '{input_name}' data, but the module itself is not present");
-            }
+/// Loads the data and the chats, the target is put back to the state it had if either fails
+fn load_or_reset<P>(target: &mut P, data: Option<Vec<u8>>, chats: &ChatStorage) -> eyre::Result<()>
+where
+    P: Persistence<Input = Vec<u8>, Output = Vec<u8>> + ?Sized,
+{
+    let fresh = target.serialize()?;
+    let load = || {
+        if let Some(data) = data {
+            let migrations = target.migrations();
+            target.deserialize(unseal(data, &migrations)?)?;
         }
-
-        Ok(())
+        target.load_chats(chats)
+    };
+    let loaded = load();
+    if loaded.is_err() {
+        target.deserialize(fresh)?;
     }
+    loaded
 }
 
 fn message_to_string(msg: &Message) -> String {
@@ -354,3 +883,183 @@ fn message_to_string(msg: &Message) -> String {
     }
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::{command::BotCommandInfo, registry::CommandSpec},
+        module::Module,
+        storage::{tests::TempDir, Storage},
+    };
+    use async_trait::async_trait;
+    use eyre::ensure;
+
+    /// Keeps a single number
+    #[derive(Default)]
+    struct Counter {
+        value: u32,
+    }
+
+    #[async_trait]
+    impl Module for Counter {
+        fn commands(&self) -> Vec<CommandSpec> {
+            vec![]
+        }
+
+        async fn try_execute_command(
+            &self,
+            _comm: &dyn Communicate,
+            _cmd: &BotCommandInfo,
+            _message: &Message,
+        ) -> eyre::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Persistence for Counter {
+        type Input = Vec<u8>;
+        type Output = Vec<u8>;
+
+        fn serialize(&self) -> eyre::Result<Self::Output> {
+            Ok(bincode::encode_to_vec(
+                self.value,
+                bincode::config::standard(),
+            )?)
+        }
+
+        /// Takes the number before checking the rest of the data
+        fn deserialize(&mut self, input: Self::Input) -> eyre::Result<()> {
+            let read;
+            (self.value, read) = bincode::decode_from_slice(&input, bincode::config::standard())?;
+            ensure!(
+                read == input.len(),
+                "{} bytes left over",
+                input.len() - read
+            );
+            Ok(())
+        }
+
+        fn export(&self) -> eyre::Result<serde_json::Value> {
+            Ok(self.value.into())
+        }
+
+        fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+            self.value = serde_json::from_value(value)?;
+            Ok(())
+        }
+    }
+
+    impl PersistentModule for Counter {}
+
+    fn counter_data(value: u32) -> Vec<u8> {
+        seal(0, Counter { value }.serialize().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn corrupt_data_is_set_aside() {
+        let dir = TempDir::new("quarantine");
+        let path = dir.join("jab.data");
+
+        let mut corrupt = Counter { value: 5 }.serialize().unwrap();
+        corrupt.push(0);
+        let corrupt = seal(0, corrupt).unwrap();
+        let mut storage = FileStorage::open(&path, 0).unwrap();
+        storage
+            .put(MODULES_NAMESPACE, "good", counter_data(7))
+            .unwrap();
+        storage
+            .put(MODULES_NAMESPACE, "bad", corrupt.clone())
+            .unwrap();
+        storage
+            .put(BOT_NAMESPACE, CHAT_SETTINGS_KEY, b"corrupt".to_vec())
+            .unwrap();
+        let throttle = seal(0, Throttle::default().serialize().unwrap()).unwrap();
+        storage
+            .put(BOT_NAMESPACE, THROTTLE_KEY, throttle.clone())
+            .unwrap();
+        storage.flush().unwrap();
+
+        let (_state_tx, state_rx) = mpsc::channel(1);
+        let config = BotConfig {
+            work_dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let mut bot = Bot::with_config("token", state_rx, config).unwrap();
+        bot.add_module("good", Counter::default());
+        bot.add_module("bad", Counter { value: 1 });
+        bot.load_data(true).unwrap();
+
+        assert_eq!(bot.modules["good"].export().unwrap(), 7);
+        // the number taken before the failure is dropped
+        assert_eq!(bot.modules["bad"].export().unwrap(), 1);
+        assert_eq!(
+            bot.quarantined,
+            HashSet::from(["bad".into(), "jab.chat_settings".into()])
+        );
+        assert!(bot.unsaved.is_empty());
+        let quarantine = |name: &str| dir.join(format!("jab.data.{name}.quarantine"));
+        assert_eq!(std::fs::read(quarantine("bad")).unwrap(), corrupt);
+        assert_eq!(
+            std::fs::read(quarantine("jab.chat_settings")).unwrap(),
+            b"corrupt"
+        );
+
+        bot.save_data().unwrap();
+        let storage = FileStorage::open(&path, 0).unwrap();
+        let get = |namespace, key| storage.get(namespace, key).unwrap().unwrap();
+        assert_eq!(get(MODULES_NAMESPACE, "good"), counter_data(7));
+        assert_eq!(get(MODULES_NAMESPACE, "bad"), counter_data(1));
+        assert_ne!(get(BOT_NAMESPACE, CHAT_SETTINGS_KEY), b"corrupt");
+        assert_eq!(get(BOT_NAMESPACE, THROTTLE_KEY), throttle);
+    }
+
+    #[test]
+    fn corrupt_data_is_left_out_of_export() {
+        let dir = TempDir::new("export");
+        let mut storage = FileStorage::open(dir.join("jab.data"), 0).unwrap();
+        storage
+            .put(MODULES_NAMESPACE, "good", counter_data(7))
+            .unwrap();
+        storage
+            .put(MODULES_NAMESPACE, "bad", b"corrupt".to_vec())
+            .unwrap();
+        storage.flush().unwrap();
+
+        let (_state_tx, state_rx) = mpsc::channel(1);
+        let config = BotConfig {
+            work_dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let mut bot = Bot::with_config("token", state_rx, config).unwrap();
+        bot.add_module("good", Counter::default());
+        bot.add_module("bad", Counter::default());
+        let exported = bot.export_data().unwrap();
+        assert_eq!(exported["modules"], serde_json::json!({ "good": 7 }));
+        assert_eq!(exported["quarantined"], serde_json::json!(["bad"]));
+        assert!(!dir.join("jab.data.bad.quarantine").exists());
+    }
+
+    #[tokio::test]
+    async fn replay_leaves_the_data_as_it_was() {
+        let dir = TempDir::new("replay");
+        let journal = dir.join("journal.jsonl");
+        std::fs::write(&journal, "").unwrap();
+
+        let (_state_tx, state_rx) = mpsc::channel(1);
+        let config = BotConfig {
+            work_dir: dir.to_path_buf(),
+            journal: Some(journal),
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut bot = Bot::with_config("token", state_rx, config).unwrap();
+        bot.add_module("counter", Counter { value: 3 });
+        let sent = bot.comm().send_message("hi", (-100).into()).await.unwrap();
+        assert_eq!(sent.into_result().unwrap().chat.id, -100);
+        bot.commit_update(10);
+        bot.save_data().unwrap();
+        assert!(!dir.join("jab.data").exists());
+        assert!(!bot.offset_file_path().exists());
+    }
+}
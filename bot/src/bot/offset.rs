@@ -0,0 +1,64 @@
+use crate::persistence::write_atomically;
+use api::basic_types::UpdateId;
+use log::error;
+use std::path::PathBuf;
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Persists the update offset in the background so that the main loop never waits on the disk,
+/// offsets committed while one is being written are coalesced into the latest
+pub(crate) struct OffsetWriter {
+    tx: watch::Sender<UpdateId>,
+    task: JoinHandle<()>,
+}
+
+impl OffsetWriter {
+    pub fn spawn(path: PathBuf, offset: UpdateId) -> Self {
+        let (tx, mut rx) = watch::channel(offset);
+        let task = tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let offset = *rx.borrow_and_update();
+                let path = path.clone();
+                let written = tokio::task::spawn_blocking(move || {
+                    write_atomically(&path, offset.to_string().as_bytes())
+                })
+                .await;
+                match written {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => error!("failed to persist update offset {offset}, {err}"),
+                    Err(err) => error!("failed to persist update offset {offset}, {err}"),
+                }
+            }
+        });
+        Self { tx, task }
+    }
+
+    pub fn write(&self, offset: UpdateId) {
+        self.tx.send_replace(offset);
+    }
+
+    /// Waits for the last offset to be written
+    pub async fn finish(self) {
+        drop(self.tx);
+        if let Err(err) = self.task.await {
+            error!("update offset writer failed, {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    #[tokio::test]
+    async fn last_offset_is_written() {
+        let dir = TempDir::new("offset");
+        let path = dir.join("jab.data.offset");
+        let writer = OffsetWriter::spawn(path.clone(), 1);
+        for offset in 2..=10 {
+            writer.write(offset);
+        }
+        writer.finish().await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "10");
+    }
+}
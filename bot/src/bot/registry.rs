@@ -0,0 +1,271 @@
+use crate::bot::throttle::Limits;
+use api::proto::{BotCommand, ChatType};
+use compact_str::{CompactString, ToCompactString};
+use log::{error, warn};
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+/// Command a module reacts to, declared once and used for routing, /help and the bot menu
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: CompactString,
+    /// Other names the command is called by, matched case-insensitively as the name
+    pub aliases: Vec<CompactString>,
+    pub description: CompactString,
+    /// Arguments usage shown in /help, e.g. `<query>`
+    pub usage: Option<CompactString>,
+    /// Chat types the command is available in, any if empty
+    pub chat_types: Vec<ChatType>,
+    pub permission: Permission,
+    /// Cooldowns and quotas for commands that are expensive to call
+    pub limits: Limits,
+}
+
+/// Who may call a command, bot owners may call any
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Permission {
+    #[default]
+    Anyone,
+    /// Users given the role in the chat by its admins, the admins themselves too
+    Role(CompactString),
+    /// Chat administrators, anyone in a private chat with the bot
+    ChatAdmin,
+    /// Users listed as the bot owners in the config
+    Owner,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Anyone => write!(f, "anyone"),
+            Permission::Role(role) => write!(f, "{role} users"),
+            Permission::ChatAdmin => write!(f, "chat admins"),
+            Permission::Owner => write!(f, "bot owners"),
+        }
+    }
+}
+
+impl CommandSpec {
+    pub fn new(name: &str, description: &str) -> Self {
+        Self {
+            name: name.into(),
+            aliases: vec![],
+            description: description.into(),
+            usage: None,
+            chat_types: vec![],
+            permission: Permission::Anyone,
+            limits: Default::default(),
+        }
+    }
+
+    pub fn aliases(mut self, aliases: &[&str]) -> Self {
+        self.aliases
+            .extend(aliases.iter().map(|alias| alias.to_compact_string()));
+        self
+    }
+
+    pub fn usage(mut self, usage: &str) -> Self {
+        self.usage = Some(usage.into());
+        self
+    }
+
+    pub fn chat_types(mut self, chat_types: &[ChatType]) -> Self {
+        self.chat_types = chat_types.to_vec();
+        self
+    }
+
+    pub fn admin_only(self) -> Self {
+        self.permission(Permission::ChatAdmin)
+    }
+
+    pub fn owner_only(self) -> Self {
+        self.permission(Permission::Owner)
+    }
+
+    /// For users with the role in the chat
+    pub fn role(self, role: &str) -> Self {
+        self.permission(Permission::Role(role.into()))
+    }
+
+    pub fn permission(mut self, permission: Permission) -> Self {
+        self.permission = permission;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn is_available_in(&self, chat_type: ChatType) -> bool {
+        self.chat_types.is_empty() || self.chat_types.contains(&chat_type)
+    }
+}
+
+/// Commands of all the modules, every name and alias belongs to a single module
+#[derive(Debug, Default)]
+pub struct CommandRegistry {
+    /// Module name and command spec in registration order
+    commands: Vec<(CompactString, CommandSpec)>,
+    /// Lowercase name or alias to the index in `commands`
+    lookup: HashMap<CompactString, usize>,
+}
+
+impl CommandRegistry {
+    /// Names and aliases taken by another module already are skipped,
+    /// returns the skipped ones
+    pub fn register(&mut self, module: &str, commands: Vec<CommandSpec>) -> Vec<CompactString> {
+        let mut conflicts = vec![];
+        for command in commands {
+            let index = self.commands.len();
+            let mut registered = false;
+            for name in std::iter::once(&command.name).chain(command.aliases.iter()) {
+                let key = name.to_lowercase().to_compact_string();
+                match self.lookup.get(&key) {
+                    Some(&other) if other != index => {
+                        let (other_module, other_command) = &self.commands[other];
+                        error!(
+                            "'{name}' of module '{module}' conflicts with \
+                            '{}' of module '{other_module}', ignored",
+                            other_command.name
+                        );
+                        conflicts.push(name.clone());
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.lookup.insert(key, index);
+                        registered = true;
+                    }
+                }
+            }
+            if registered {
+                self.commands.push((module.into(), command));
+            } else {
+                warn!(
+                    "command '{}' of module '{module}' is not reachable, all its names are taken",
+                    command.name
+                );
+            }
+        }
+        conflicts
+    }
+
+    /// Finds the module and the command called by the name or an alias
+    pub fn resolve(&self, name: &str) -> Option<(&str, &CommandSpec)> {
+        let index = self.lookup.get(name.to_lowercase().as_str())?;
+        let (module, command) = &self.commands[*index];
+        Some((module.as_str(), command))
+    }
+
+    /// Lists the commands of the modules `is_enabled` returns true for
+    pub fn help(&self, chat_type: ChatType, is_enabled: impl Fn(&str) -> bool) -> String {
+        let mut help = String::new();
+        let mut last_module = None;
+        for (module, command) in &self.commands {
+            if !command.is_available_in(chat_type) || !is_enabled(module) {
+                continue;
+            }
+            if last_module != Some(module) {
+                if last_module.is_some() {
+                    help.push('\n');
+                }
+                let _ = writeln!(help, "{module}");
+                last_module = Some(module);
+            }
+            let _ = write!(help, "/{}", command.name);
+            if let Some(usage) = &command.usage {
+                let _ = write!(help, " {usage}");
+            }
+            let _ = write!(help, " - {}", command.description);
+            if command.permission != Permission::Anyone {
+                let _ = write!(help, " ({} only)", command.permission);
+            }
+            if !command.aliases.is_empty() {
+                let _ = write!(help, " (also {})", command.aliases.join(", "));
+            }
+            help.push('\n');
+        }
+        help
+    }
+
+    /// Commands for the bot menu, those with names Telegram does not accept are left out
+    pub fn bot_commands(&self) -> Vec<BotCommand> {
+        self.commands
+            .iter()
+            .map(|(_, command)| command)
+            .filter(|command| {
+                command.permission == Permission::Anyone && is_valid_bot_command(&command.name)
+            })
+            .map(|command| BotCommand {
+                command: command.name.clone(),
+                description: command.description.chars().take(256).collect(),
+            })
+            .collect()
+    }
+}
+
+fn is_valid_bot_command(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_aliases_ignoring_case() {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            "imager",
+            vec![CommandSpec::new("pls", "random picture").aliases(&["плс"])],
+        );
+        assert_eq!(registry.resolve("PLS").unwrap().1.name, "pls");
+        assert_eq!(registry.resolve("Плс").unwrap().0, "imager");
+        assert!(registry.resolve("gif").is_none());
+    }
+
+    #[test]
+    fn conflicting_aliases_are_skipped() {
+        let mut registry = CommandRegistry::default();
+        registry.register("imager", vec![CommandSpec::new("pls", "picture")]);
+        let conflicts = registry.register(
+            "gigachat",
+            vec![
+                CommandSpec::new("gpt", "ask").aliases(&["Pls"]),
+                CommandSpec::new("pls", "ask"),
+            ],
+        );
+        assert_eq!(conflicts, vec!["Pls", "pls"]);
+        assert_eq!(registry.resolve("pls").unwrap().0, "imager");
+        assert_eq!(registry.resolve("gpt").unwrap().0, "gigachat");
+        assert_eq!(registry.bot_commands().len(), 2);
+    }
+
+    #[test]
+    fn help_lists_commands_available_in_chat() {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            "archivarius",
+            vec![
+                CommandSpec::new("save", "save the replied message"),
+                CommandSpec::new("dev_save", "listen to another chat")
+                    .usage("<chat id>")
+                    .chat_types(&[ChatType::Private])
+                    .admin_only(),
+            ],
+        );
+        let help = registry.help(ChatType::Group, |_| true);
+        assert!(help.contains("/save - save the replied message"));
+        assert!(!help.contains("dev_save"));
+        let help = registry.help(ChatType::Private, |_| true);
+        assert!(help.contains("/dev_save <chat id> - listen to another chat (chat admins only)"));
+        assert!(registry
+            .help(ChatType::Private, |module| module != "archivarius")
+            .is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    bot::{chat_settings::SettingSpec, SharedModule},
+    communicator::SharedCommunicator,
+};
+use compact_str::CompactString;
+use eyre::eyre;
+use log::{error, warn};
+use std::{
+    any::Any,
+    future::Future,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
+};
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    /// Max time a module may spend on a single update
+    pub timeout: Duration,
+    /// Consecutive timeouts or panics after which the module is disabled,
+    /// none to never disable
+    pub failure_limit: Option<u32>,
+    /// Whether to tell the chat that its command failed
+    pub reply_on_error: bool,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            failure_limit: None,
+            reply_on_error: false,
+        }
+    }
+}
+
+/// Runs every module invocation in its own task, so that a hung or panicking
+/// module cannot stall or kill the bot
+pub(crate) struct SupervisedModule {
+    name: CompactString,
+    module: SharedModule,
+    config: SupervisorConfig,
+    failures: AtomicU32,
+    disabled: AtomicBool,
+}
+
+impl SupervisedModule {
+    pub fn new(name: CompactString, module: SharedModule, config: SupervisorConfig) -> Self {
+        Self {
+            name,
+            module,
+            config,
+            failures: AtomicU32::new(0),
+            disabled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn settings(&self) -> Vec<SettingSpec> {
+        self.module.settings()
+    }
+
+    /// Runs the hook produced by `make_hook` for the module,
+    /// does nothing if the module was disabled
+    pub async fn run<F, Fut>(&self, comm: &SharedCommunicator, make_hook: F) -> eyre::Result<()>
+    where
+        F: FnOnce(SharedModule, SharedCommunicator) -> Fut,
+        Fut: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        if self.disabled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let task = tokio::spawn(make_hook(self.module.clone(), comm.clone()));
+        let abort_handle = task.abort_handle();
+
+        match tokio::time::timeout(self.config.timeout, task).await {
+            Ok(Ok(result)) => {
+                self.failures.store(0, Ordering::Relaxed);
+                result
+            }
+            Ok(Err(err)) if err.is_panic() => {
+                let report = eyre!("panicked, {}", panic_message(err.into_panic().as_ref()));
+                self.on_failure();
+                Err(report)
+            }
+            Ok(Err(err)) => Err(eyre!("task was cancelled, {err}")),
+            Err(_) => {
+                abort_handle.abort();
+                self.on_failure();
+                Err(eyre!("timed out after {:?}", self.config.timeout))
+            }
+        }
+    }
+
+    fn on_failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let Some(limit) = self.config.failure_limit else {
+            return;
+        };
+        if failures >= limit && !self.disabled.swap(true, Ordering::Relaxed) {
+            error!(
+                "module '{}' disabled after {failures} consecutive failures",
+                self.name
+            );
+        } else if failures + 1 == limit {
+            warn!(
+                "module '{}' will be disabled after one more failure",
+                self.name
+            );
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown panic payload"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bot::{command::BotCommandInfo, registry::CommandSpec},
+        communicator::{Communicate, Communicator},
+        module::{Module, PersistentModule},
+        persistence::Persistence,
+    };
+    use api::proto::Message;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Faulty {
+        hang: bool,
+    }
+
+    #[async_trait]
+    impl Module for Faulty {
+        fn commands(&self) -> Vec<CommandSpec> {
+            vec![CommandSpec::new("test", "fails")]
+        }
+
+        async fn try_execute_command(
+            &self,
+            _comm: &dyn Communicate,
+            _cmd: &BotCommandInfo,
+            _message: &Message,
+        ) -> eyre::Result<()> {
+            if self.hang {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            }
+            panic!("faulty module");
+        }
+    }
+
+    impl Persistence for Faulty {
+        type Input = Vec<u8>;
+        type Output = Vec<u8>;
+
+        fn serialize(&self) -> eyre::Result<Self::Output> {
+            Ok(vec![])
+        }
+
+        fn deserialize(&mut self, _input: Self::Input) -> eyre::Result<()> {
+            Ok(())
+        }
+
+        fn export(&self) -> eyre::Result<serde_json::Value> {
+            Ok(serde_json::Value::Null)
+        }
+
+        fn import(&mut self, _value: serde_json::Value) -> eyre::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl PersistentModule for Faulty {}
+
+    async fn run(module: &SupervisedModule) -> eyre::Result<()> {
+        module
+            .run(&comm(), |module, comm| async move {
+                let message = Message {
+                    text: Some("/test".into()),
+                    ..Default::default()
+                };
+                let cmd = BotCommandInfo::try_from(&message)?;
+                module
+                    .try_execute_command(comm.as_ref(), &cmd, &message)
+                    .await
+            })
+            .await
+    }
+
+    fn comm() -> SharedCommunicator {
+        Arc::new(Communicator::new("token"))
+    }
+
+    #[tokio::test]
+    async fn panic_is_reported_and_module_disabled() {
+        let module = SupervisedModule::new(
+            "faulty".into(),
+            Arc::new(Faulty { hang: false }),
+            SupervisorConfig {
+                failure_limit: Some(2),
+                ..Default::default()
+            },
+        );
+        let err = run(&module).await.unwrap_err();
+        assert!(err.to_string().contains("faulty module"));
+        assert!(run(&module).await.is_err());
+        assert!(run(&module).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn hung_module_times_out() {
+        let module = SupervisedModule::new(
+            "faulty".into(),
+            Arc::new(Faulty { hang: true }),
+            SupervisorConfig {
+                timeout: Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+        let err = run(&module).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+}
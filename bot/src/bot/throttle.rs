@@ -0,0 +1,343 @@
+use crate::persistence::Persistence;
+use api::basic_types::{ChatIntId, UserId};
+use bincode::{Decode, Encode};
+use chrono::{Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// Calls allowed in a row, then one more every `interval`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rate {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl Rate {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self { burst, interval }
+    }
+}
+
+/// Limits of a command, none by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub per_user: Option<Rate>,
+    pub per_chat: Option<Rate>,
+    /// Calls a user may make per UTC day
+    pub daily_per_user: Option<u32>,
+    /// Calls a chat may make per UTC day
+    pub daily_per_chat: Option<u32>,
+    /// Commands of the same group are counted together, each command on its own otherwise
+    pub group: Option<&'static str>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.per_user.is_none()
+            && self.per_chat.is_none()
+            && self.daily_per_user.is_none()
+            && self.daily_per_chat.is_none()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Encode, Decode, Serialize, Deserialize)]
+enum Scope {
+    User(UserId),
+    Chat(ChatIntId),
+}
+
+type Key = (String, Scope);
+
+/// Token bucket, `tokens` as of `updated_at`
+#[derive(Debug, Copy, Clone, Encode, Decode, Serialize, Deserialize)]
+struct Bucket {
+    tokens: f64,
+    /// Unix time, ms
+    updated_at: i64,
+    /// When the bucket is full again and may be forgotten
+    full_at: i64,
+}
+
+#[derive(Debug, Copy, Clone, Encode, Decode, Serialize, Deserialize)]
+struct Quota {
+    /// Days since the common era, UTC
+    day: i32,
+    used: u32,
+}
+
+/// Cooldowns and daily quotas of the commands, the counters are persisted
+#[derive(Debug, Default)]
+pub struct Throttle {
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug, Default, Encode, Decode)]
+struct ThrottleState {
+    buckets: HashMap<Key, Bucket>,
+    quotas: HashMap<Key, Quota>,
+}
+
+/// JSON objects cannot have the keys made of a command and a scope, so the counters are listed
+#[derive(Serialize, Deserialize)]
+struct ExportedState {
+    buckets: Vec<(Key, Bucket)>,
+    quotas: Vec<(Key, Quota)>,
+}
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+impl Throttle {
+    /// Counts the call if every limit allows it, otherwise returns how long to wait
+    pub fn check(
+        &self,
+        command: &str,
+        limits: &Limits,
+        chat_id: ChatIntId,
+        user_id: Option<UserId>,
+    ) -> Result<(), Duration> {
+        self.check_at(
+            command,
+            limits,
+            chat_id,
+            user_id,
+            Utc::now().timestamp_millis(),
+        )
+    }
+
+    fn check_at(
+        &self,
+        command: &str,
+        limits: &Limits,
+        chat_id: ChatIntId,
+        user_id: Option<UserId>,
+        now: i64,
+    ) -> Result<(), Duration> {
+        let command = limits.group.unwrap_or(command);
+        let mut rates = vec![];
+        let mut quotas = vec![];
+        if let Some(rate) = limits.per_chat {
+            rates.push((Scope::Chat(chat_id), rate));
+        }
+        if let Some(limit) = limits.daily_per_chat {
+            quotas.push((Scope::Chat(chat_id), limit));
+        }
+        // channel posts and anonymous admins have no user to count
+        if let Some(user_id) = user_id {
+            if let Some(rate) = limits.per_user {
+                rates.push((Scope::User(user_id), rate));
+            }
+            if let Some(limit) = limits.daily_per_user {
+                quotas.push((Scope::User(user_id), limit));
+            }
+        }
+
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        let today = day_of(now);
+        let mut wait = Duration::ZERO;
+        for (scope, rate) in &rates {
+            let bucket = refill(
+                state.buckets.get(&(command.into(), *scope)).copied(),
+                *rate,
+                now,
+            );
+            if bucket.tokens < 1.0 {
+                wait = wait.max(rate.interval.mul_f64(1.0 - bucket.tokens));
+            }
+        }
+        for (scope, limit) in &quotas {
+            let used = match state.quotas.get(&(command.into(), *scope)) {
+                Some(quota) if quota.day == today => quota.used,
+                _ => 0,
+            };
+            if used >= *limit {
+                let next_day = (now.div_euclid(DAY_MS) + 1) * DAY_MS;
+                wait = wait.max(Duration::from_millis((next_day - now) as u64));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for (scope, rate) in rates {
+            let key = (command.to_string(), scope);
+            let mut bucket = refill(state.buckets.get(&key).copied(), rate, now);
+            bucket.tokens -= 1.0;
+            let missing = rate.burst.max(1) as f64 - bucket.tokens;
+            bucket.full_at = now + rate.interval.mul_f64(missing).as_millis() as i64;
+            state.buckets.insert(key, bucket);
+        }
+        for (scope, _) in quotas {
+            let quota = state
+                .quotas
+                .entry((command.to_string(), scope))
+                .or_insert(Quota {
+                    day: today,
+                    used: 0,
+                });
+            if quota.day != today {
+                *quota = Quota {
+                    day: today,
+                    used: 0,
+                };
+            }
+            quota.used += 1;
+        }
+        Ok(())
+    }
+}
+
+fn refill(bucket: Option<Bucket>, rate: Rate, now: i64) -> Bucket {
+    let burst = rate.burst.max(1) as f64;
+    let Some(bucket) = bucket else {
+        return Bucket {
+            tokens: burst,
+            updated_at: now,
+            full_at: now,
+        };
+    };
+    let elapsed = Duration::from_millis((now - bucket.updated_at).max(0) as u64);
+    let refilled = elapsed.as_secs_f64() / rate.interval.as_secs_f64().max(f64::EPSILON);
+    Bucket {
+        tokens: (bucket.tokens + refilled).min(burst),
+        updated_at: now,
+        full_at: bucket.full_at,
+    }
+}
+
+fn day_of(unix_ms: i64) -> i32 {
+    chrono::DateTime::from_timestamp_millis(unix_ms)
+        .map(|time| time.num_days_from_ce())
+        .unwrap_or_default()
+}
+
+/// `42s`, `5m` or `3h 20m`
+pub fn format_wait(wait: Duration) -> String {
+    let seconds = wait.as_secs().max(1);
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m", seconds.div_ceil(60)),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+impl Persistence for Throttle {
+    type Input = Vec<u8>;
+    type Output = Vec<u8>;
+
+    fn serialize(&self) -> eyre::Result<Self::Output> {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+        // full buckets and quotas of past days are the same as none
+        let now = Utc::now().timestamp_millis();
+        let today = day_of(now);
+        state.quotas.retain(|_, quota| quota.day == today);
+        state.buckets.retain(|_, bucket| bucket.full_at > now);
+        Ok(bincode::encode_to_vec(
+            &*state,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn deserialize(&mut self, input: Self::Input) -> eyre::Result<()> {
+        let state = bincode::decode_from_slice::<ThrottleState, _>(
+            input.as_slice(),
+            bincode::config::standard(),
+        )?
+        .0;
+        self.state = Mutex::new(state);
+        Ok(())
+    }
+
+    fn export(&self) -> eyre::Result<serde_json::Value> {
+        let state = self.state.lock().expect("throttle lock poisoned");
+        Ok(serde_json::to_value(ExportedState {
+            buckets: state.buckets.clone().into_iter().collect(),
+            quotas: state.quotas.clone().into_iter().collect(),
+        })?)
+    }
+
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        let state = serde_json::from_value::<ExportedState>(value)?;
+        self.state = Mutex::new(ThrottleState {
+            buckets: state.buckets.into_iter().collect(),
+            quotas: state.quotas.into_iter().collect(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let throttle = Throttle::default();
+        let limits = Limits {
+            per_user: Some(Rate::new(2, Duration::from_secs(10))),
+            ..Default::default()
+        };
+        let check = |now| throttle.check_at("gpt", &limits, 1, Some(7), now);
+        assert!(check(0).is_ok());
+        assert!(check(0).is_ok());
+        assert_eq!(check(5_000), Err(Duration::from_secs(5)));
+        assert!(check(10_000).is_ok());
+        // other users have their own buckets
+        assert!(throttle
+            .check_at("gpt", &limits, 1, Some(8), 10_000)
+            .is_ok());
+    }
+
+    #[test]
+    fn daily_quota_resets_next_day() {
+        let throttle = Throttle::default();
+        let limits = Limits {
+            daily_per_chat: Some(1),
+            per_user: Some(Rate::new(1, Duration::from_millis(2 * DAY_MS as u64))),
+            ..Default::default()
+        };
+        let noon = DAY_MS / 2;
+        assert!(throttle.check_at("pls", &limits, 1, Some(7), noon).is_ok());
+        assert_eq!(
+            throttle.check_at("pls", &limits, 1, Some(8), noon),
+            Err(Duration::from_millis(DAY_MS as u64 / 2))
+        );
+        // a denied call does not spend the user's bucket
+        assert!(throttle
+            .check_at("pls", &limits, 1, Some(8), DAY_MS)
+            .is_ok());
+        assert_eq!(format_wait(Duration::from_secs(2 * 3600 + 125)), "2h 2m");
+    }
+
+    #[test]
+    fn counters_survive_export() {
+        let limits = Limits {
+            per_chat: Some(Rate::new(1, Duration::from_secs(60))),
+            ..Default::default()
+        };
+        let throttle = Throttle::default();
+        assert!(throttle.check_at("pls", &limits, 1, None, 0).is_ok());
+        let mut imported = Throttle::default();
+        imported.import(throttle.export().unwrap()).unwrap();
+        assert!(imported.check_at("pls", &limits, 1, None, 0).is_err());
+    }
+
+    #[test]
+    fn group_shares_the_counters() {
+        let limits = Limits {
+            daily_per_chat: Some(2),
+            group: Some("search"),
+            ..Default::default()
+        };
+        let throttle = Throttle::default();
+        assert!(throttle.check_at("pls", &limits, 1, None, 0).is_ok());
+        assert!(throttle.check_at("gif", &limits, 1, None, 0).is_ok());
+        assert!(throttle.check_at("pls", &limits, 1, None, 0).is_err());
+        assert!(throttle.check_at("gif", &limits, 1, None, 0).is_err());
+        // other commands have their own
+        let own = Limits {
+            group: None,
+            ..limits
+        };
+        assert!(throttle.check_at("gif", &own, 1, None, 0).is_ok());
+    }
+}
@@ -1,20 +1,26 @@
 use crate::connector::{polling::PollingConnector, Connector};
 use api::{
+    basic_types::UserId,
     basic_types::{MessageId, MessageThreadId},
     endpoints::{
-        CopyMessage, DeleteMessage, ForwardMessage, SendAnimation, SendChatAction, SendMessage,
-        SendPhoto,
+        CopyMessage, DeleteMessage, Empty, ForwardMessage, GetChatMember, GetMe, SendAnimation,
+        SendChatAction, SendMessage, SendPhoto, SetMyCommands,
+    },
+    proto::{
+        BotCommand, ChatAction, ChatId, ChatMember, Message, MessageEntity, ParseMode, ReplyMarkup,
+        User,
     },
-    proto::{ChatAction, ChatId, Message, MessageEntity, ParseMode, ReplyMarkup},
     request::{
-        CopyMessageRequest, DeleteMessageRequest, ForwardMessageRequest, SendAnimationRequest,
-        SendChatActionRequest, SendMessageRequest, SendPhotoRequest,
+        CopyMessageRequest, DeleteMessageRequest, ForwardMessageRequest, GetChatMemberRequest,
+        SendAnimationRequest, SendChatActionRequest, SendMessageRequest, SendPhotoRequest,
+        SetMyCommandsRequest,
     },
     response::{CommonResponse, MessageIdResponse},
 };
 use async_trait::async_trait;
 use compact_str::{CompactString, ToCompactString};
 use eyre::eyre;
+use log::info;
 use std::sync::Arc;
 
 #[async_trait]
@@ -85,8 +91,24 @@ pub trait Communicate: Send + Sync {
         chat_id: ChatId,
         message_id: MessageId,
     ) -> eyre::Result<CommonResponse<bool>>;
+
+    async fn set_my_commands(
+        &self,
+        commands: Vec<BotCommand>,
+    ) -> eyre::Result<CommonResponse<bool>>;
+
+    async fn get_me(&self) -> eyre::Result<CommonResponse<User>>;
+
+    async fn get_chat_member(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> eyre::Result<CommonResponse<ChatMember>>;
 }
 
+/// Communicator shared by the tasks handling updates
+pub type SharedCommunicator = Arc<dyn Communicate>;
+
 #[derive(Clone)]
 pub struct Communicator {
     token: Arc<CompactString>,
@@ -100,27 +122,26 @@ impl Communicator {
     }
 }
 
-impl Communicator {
-    pub(crate) async fn del(&self, message: &Message) -> eyre::Result<bool> {
-        let requested_message = message
-            .reply_to_message
-            .as_ref()
-            .ok_or(eyre!("replied message does not exist to delete"))?;
-        let requested_message_deleted = self
-            .delete_message(
-                requested_message.chat.id.into(),
-                requested_message.message_id,
-            )
-            .await?
-            .into_result()?;
-        // let command_message_deleted = self
-        //     .delete_message(message.chat.id.into(), message.message_id)
-        //     .await?
-        //     .into_result()?;
-        Ok(
-            requested_message_deleted, /* && command_message_deleted*/
+/// Deletes the message the command replies to
+pub(crate) async fn del(comm: &dyn Communicate, message: &Message) -> eyre::Result<bool> {
+    let requested_message = message
+        .reply_to_message
+        .as_ref()
+        .ok_or(eyre!("replied message does not exist to delete"))?;
+    let requested_message_deleted = comm
+        .delete_message(
+            requested_message.chat.id.into(),
+            requested_message.message_id,
         )
-    }
+        .await?
+        .into_result()?;
+    // let command_message_deleted = self
+    //     .delete_message(message.chat.id.into(), message.message_id)
+    //     .await?
+    //     .into_result()?;
+    Ok(
+        requested_message_deleted, /* && command_message_deleted*/
+    )
 }
 
 #[async_trait]
@@ -275,4 +296,163 @@ impl Communicate for Communicator {
         };
         PollingConnector::send_request::<DeleteMessage>(&self.token, &request, None).await
     }
+
+    async fn set_my_commands(
+        &self,
+        commands: Vec<BotCommand>,
+    ) -> eyre::Result<CommonResponse<bool>> {
+        let request = SetMyCommandsRequest {
+            commands,
+            language_code: None,
+        };
+        PollingConnector::send_request::<SetMyCommands>(&self.token, &request, None).await
+    }
+
+    async fn get_me(&self) -> eyre::Result<CommonResponse<User>> {
+        PollingConnector::send_request::<GetMe>(&self.token, &Empty, None).await
+    }
+
+    async fn get_chat_member(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> eyre::Result<CommonResponse<ChatMember>> {
+        let request = GetChatMemberRequest { chat_id, user_id };
+        PollingConnector::send_request::<GetChatMember>(&self.token, &request, None).await
+    }
+}
+
+/// Logs the messages instead of sending them, as when a journal is replayed against live chats;
+/// what it returns for them is made up, only `get_me` and `get_chat_member` reach Telegram
+#[derive(Clone)]
+pub struct DryRunCommunicator {
+    communicator: Communicator,
+}
+
+impl DryRunCommunicator {
+    pub fn new(token: &str) -> Self {
+        Self {
+            communicator: Communicator::new(token),
+        }
+    }
+}
+
+/// A message with nothing but the chat it was to be sent to
+fn not_sent(chat_id: &ChatId) -> CommonResponse<Message> {
+    let mut message = Message::default();
+    if let ChatId::Int(id) = chat_id {
+        message.chat.id = *id;
+    }
+    CommonResponse::Ok(message)
+}
+
+#[async_trait]
+impl Communicate for DryRunCommunicator {
+    async fn send_message(
+        &self,
+        text: &str,
+        chat_id: ChatId,
+    ) -> eyre::Result<CommonResponse<Message>> {
+        info!("dry run, not sending to {chat_id:?}: {text}");
+        Ok(not_sent(&chat_id))
+    }
+
+    async fn reply_message(
+        &self,
+        text: &str,
+        chat_id: ChatId,
+        reply_to_message_id: MessageId,
+        _parse_mode: Option<ParseMode>,
+    ) -> eyre::Result<CommonResponse<Message>> {
+        info!("dry run, not replying to #{reply_to_message_id} in {chat_id:?}: {text}");
+        Ok(not_sent(&chat_id))
+    }
+
+    async fn send_photo_url(
+        &self,
+        url: &str,
+        chat_id: ChatId,
+        _reply_to_message_id: Option<MessageId>,
+    ) -> eyre::Result<CommonResponse<Message>> {
+        info!("dry run, not sending photo to {chat_id:?}: {url}");
+        Ok(not_sent(&chat_id))
+    }
+
+    async fn send_animation_url(
+        &self,
+        url: &str,
+        chat_id: ChatId,
+        _reply_to_message_id: Option<MessageId>,
+    ) -> eyre::Result<CommonResponse<Message>> {
+        info!("dry run, not sending animation to {chat_id:?}: {url}");
+        Ok(not_sent(&chat_id))
+    }
+
+    async fn forward_message(
+        &self,
+        to_chat_id: ChatId,
+        from_chat_id: ChatId,
+        message_id: MessageId,
+        _disable_notification: Option<bool>,
+        _protect_content: Option<bool>,
+    ) -> eyre::Result<CommonResponse<Message>> {
+        info!("dry run, not forwarding #{message_id} of {from_chat_id:?} to {to_chat_id:?}");
+        Ok(not_sent(&to_chat_id))
+    }
+
+    async fn copy_message(
+        &self,
+        chat_id: ChatId,
+        _message_thread_id: Option<MessageThreadId>,
+        from_chat_id: ChatId,
+        message_id: MessageId,
+        _caption: Option<CompactString>,
+        _parse_mode: Option<ParseMode>,
+        _caption_entities: Vec<MessageEntity>,
+        _disable_notification: Option<bool>,
+        _protect_content: Option<bool>,
+        _reply_to_message_id: Option<MessageId>,
+        _allow_sending_without_reply: Option<bool>,
+        _reply_markup: Option<ReplyMarkup>,
+    ) -> eyre::Result<CommonResponse<MessageIdResponse>> {
+        info!("dry run, not copying #{message_id} of {from_chat_id:?} to {chat_id:?}");
+        Ok(CommonResponse::Ok(MessageIdResponse { message_id: 0 }))
+    }
+
+    async fn send_chat_action(
+        &self,
+        _chat_id: ChatId,
+        _message_thread_id: Option<MessageThreadId>,
+        _action: ChatAction,
+    ) -> eyre::Result<CommonResponse<bool>> {
+        Ok(CommonResponse::Ok(true))
+    }
+
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> eyre::Result<CommonResponse<bool>> {
+        info!("dry run, not deleting #{message_id} in {chat_id:?}");
+        Ok(CommonResponse::Ok(true))
+    }
+
+    async fn set_my_commands(
+        &self,
+        _commands: Vec<BotCommand>,
+    ) -> eyre::Result<CommonResponse<bool>> {
+        Ok(CommonResponse::Ok(true))
+    }
+
+    async fn get_me(&self) -> eyre::Result<CommonResponse<User>> {
+        self.communicator.get_me().await
+    }
+
+    async fn get_chat_member(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> eyre::Result<CommonResponse<ChatMember>> {
+        self.communicator.get_chat_member(chat_id, user_id).await
+    }
 }
@@ -1,20 +1,105 @@
+use crate::connector::ConnectorMode;
 use api::proto::UpdateType;
-use serde::Deserialize;
+use compact_str::CompactString;
+use eyre::ensure;
+use log::warn;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashSet;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+/// How updates are received
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
 pub struct ConnectorConfig {
+    pub mode: ConnectorMode,
+    /// Update types the bot receives, all but a few rare ones if empty
+    #[serde(deserialize_with = "deserialize_allowed_updates")]
     pub allowed_updates: HashSet<UpdateType>,
+    /// Max number of updates fetched at once
     pub update_limit: Option<u32>,
+    /// Long polling timeout, s
     pub timeout: Option<u32>,
+    /// Whether updates that came while the bot was down are dropped
+    pub skip_missed_updates: bool,
+    pub webhook: WebhookConfig,
 }
 
-impl Default for ConnectorConfig {
+impl ConnectorConfig {
+    pub fn verify(&self) -> eyre::Result<()> {
+        ensure!(
+            matches!(self.update_limit, None | Some(1..=100)),
+            "number of updates to retrieve is strictly 1-100"
+        );
+        if matches!(self.timeout, Some(0)) {
+            warn!(
+                "timeout = 0, i.e. short polling; should be positive,
+                short polling should be used for testing purposes only"
+            );
+        }
+        if matches!(self.mode, ConnectorMode::Webhook) {
+            ensure!(
+                !self.webhook.https_url.is_empty(),
+                "webhook mode needs the https url Telegram sends updates to"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Public address Telegram sends updates to
+    pub https_url: CompactString,
+    pub ip_address: Option<CompactString>,
+    /// Address to listen on, `ip_address` or localhost if not set
+    pub listen_address: Option<CompactString>,
+    pub port: u16,
+    /// Serve https with the certificate from the work dir,
+    /// turn off when a reverse proxy terminates TLS
+    pub tls: bool,
+    /// Upload the certificate to Telegram,
+    /// turn off when the certificate is signed by a trusted CA
+    pub upload_certificate: bool,
+    /// Updates not yet fetched by the bot, Telegram is answered with 429 when it's full;
+    /// updates are kept in `{data_file_name}.queue` in the work dir until handled
+    pub queue_size: usize,
+}
+
+impl Default for WebhookConfig {
     fn default() -> Self {
         Self {
-            allowed_updates: [UpdateType::Message].into(),
-            update_limit: Some(10),
-            timeout: Some(10),
+            https_url: Default::default(),
+            ip_address: None,
+            listen_address: None,
+            port: 443,
+            tls: true,
+            upload_certificate: true,
+            queue_size: 1024,
         }
     }
 }
+
+/// `<allowed_updates><update type="message"/></allowed_updates>`
+fn deserialize_allowed_updates<'de, D>(deserializer: D) -> Result<HashSet<UpdateType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct AllowedUpdates {
+        #[serde(rename = "$value", default)]
+        updates: Vec<UpdateTypeItem>,
+    }
+
+    #[derive(Deserialize)]
+    struct UpdateTypeItem {
+        #[serde(rename = "type")]
+        update_type: UpdateType,
+    }
+
+    let allowed = AllowedUpdates::deserialize(deserializer)?;
+    Ok(allowed
+        .updates
+        .into_iter()
+        .map(|item| item.update_type)
+        .collect())
+}
@@ -0,0 +1,152 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Observable state of update fetching
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectorHealth {
+    Starting,
+    Healthy,
+    /// Fetching fails, retrying with exponential backoff
+    Degraded {
+        failures: u32,
+    },
+    /// Too many consecutive failures, retrying at the max delay
+    Down {
+        failures: u32,
+    },
+    /// Another instance is polling with the same token
+    Conflict,
+    /// The token was revoked, fetching stopped
+    Unauthorized,
+    /// No more updates are coming, e.g. the whole journal was replayed
+    Exhausted,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackoffConfig {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Relative random deviation of each delay, 0.1 means ±10%
+    pub jitter: f64,
+    /// Consecutive failures after which retries happen at the max delay only
+    pub circuit_threshold: u32,
+    /// Min delay after 409 Conflict, gives the other instance time to shut down
+    pub conflict_delay: Duration,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.1,
+            circuit_threshold: 10,
+            conflict_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Backoff {
+    config: BackoffConfig,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            failures: 0,
+        }
+    }
+
+    pub fn config(&self) -> &BackoffConfig {
+        &self.config
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.failures >= self.config.circuit_threshold
+    }
+
+    pub fn health(&self) -> ConnectorHealth {
+        match self.failures {
+            0 => ConnectorHealth::Healthy,
+            failures if self.is_open() => ConnectorHealth::Down { failures },
+            failures => ConnectorHealth::Degraded { failures },
+        }
+    }
+
+    /// Registers a failure and returns the delay before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        let max = self.config.max_delay.as_secs_f64();
+        let base = if self.is_open() {
+            max
+        } else {
+            let exponent = (self.failures - 1).min(i32::MAX as u32) as i32;
+            (self.config.initial_delay.as_secs_f64() * self.config.multiplier.powi(exponent))
+                .min(max)
+        };
+        let jitter = self.config.jitter.abs();
+        let factor = if jitter > 0.0 {
+            1.0 + rand::thread_rng().gen_range(-jitter..=jitter)
+        } else {
+            1.0
+        };
+        Duration::from_secs_f64((base * factor).clamp(0.0, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_jitter() -> BackoffConfig {
+        BackoffConfig {
+            jitter: 0.0,
+            circuit_threshold: 5,
+            max_delay: Duration::from_secs(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_max() {
+        let mut backoff = Backoff::new(no_jitter());
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(8));
+        assert_eq!(backoff.health(), ConnectorHealth::Degraded { failures: 4 });
+        assert_eq!(backoff.next_delay(), Duration::from_secs(10));
+        assert_eq!(backoff.health(), ConnectorHealth::Down { failures: 5 });
+        backoff.reset();
+        assert_eq!(backoff.health(), ConnectorHealth::Healthy);
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let mut backoff = Backoff::new(BackoffConfig {
+            jitter: 0.5,
+            ..no_jitter()
+        });
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay <= Duration::from_secs(10));
+        }
+        backoff.reset();
+        let delay = backoff.next_delay();
+        assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1500));
+    }
+}
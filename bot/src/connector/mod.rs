@@ -1,5 +1,7 @@
-pub(crate) mod config;
+pub mod config;
+pub mod health;
 pub(crate) mod polling;
+pub mod replay;
 pub(crate) mod webhook;
 
 use async_trait::async_trait;
@@ -9,8 +11,10 @@ use eyre::eyre;
 use http::HeaderMap;
 
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 use api::{
+    basic_types::UpdateId,
     endpoints::Endpoint,
     files::GetFiles,
     params::ToParams,
@@ -21,11 +25,21 @@ use api::{
 const BASE_URL: &str = "https://api.telegram.org";
 
 #[async_trait]
-pub trait Connector {
+pub trait Connector: Send {
     async fn on_startup(&mut self) -> eyre::Result<()>;
 
     async fn fetch_updates(&mut self) -> eyre::Result<Vec<CommonUpdate>>;
 
+    /// Acknowledges that the update and all the previous ones were handled,
+    /// they are not to be fetched again
+    fn commit(&mut self, update_id: UpdateId);
+
+    /// Whether no more updates are coming and all of them were committed,
+    /// fetching stops once the bot handles them
+    fn is_exhausted(&self) -> bool {
+        false
+    }
+
     fn query_url<E: Endpoint>(token: &str) -> String
     where
         Self: Sized,
@@ -113,9 +127,27 @@ pub trait Connector {
     }
 }
 
-#[derive(Debug, Display, Deserialize, Default, Copy, Clone)]
+/// The PascalCase names are kept for the configs written before
+#[derive(Debug, Display, Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum ConnectorMode {
     #[default]
+    #[serde(alias = "Polling")]
     Polling,
+    #[serde(alias = "Webhook")]
     Webhook,
 }
+
+impl FromStr for ConnectorMode {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "polling" => Ok(Self::Polling),
+            "webhook" => Ok(Self::Webhook),
+            _ => Err(eyre!(
+                "unknown connector mode '{s}', polling or webhook expected"
+            )),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::connector::Connector;
 use api::{
+    basic_types::UpdateId,
     endpoints::{DeleteWebhook, GetUpdates},
     proto::{CommonUpdate, UpdateType},
     request::{DeleteWebhookRequest, GetUpdatesRequest},
@@ -11,7 +12,8 @@ use log::{error, info};
 
 pub struct PollingConnector {
     token: CompactString,
-    last_update_id: Option<usize>,
+    /// Next update id to fetch, Telegram forgets all the previous ones once requested
+    offset: Option<usize>,
     config: PollingConnectorConfig,
 }
 
@@ -26,7 +28,7 @@ impl PollingConnector {
     pub fn with_config(token: &str, config: PollingConnectorConfig) -> Self {
         Self {
             token: token.to_compact_string(),
-            last_update_id: None,
+            offset: None,
             config,
         }
     }
@@ -57,7 +59,7 @@ impl Connector for PollingConnector {
 
     async fn fetch_updates(&mut self) -> eyre::Result<Vec<CommonUpdate>> {
         let request = GetUpdatesRequest {
-            offset: self.last_update_id,
+            offset: self.offset,
             limit: self.config.limit,
             timeout: self.config.timeout,
             allowed_updates: Some(self.config.allowed_updates.clone()),
@@ -71,11 +73,10 @@ impl Connector for PollingConnector {
         .await?
         .into_result()?;
 
-        if !updates.is_empty() {
-            let last_update_id = updates.iter().map(|u| u.id).max().unwrap();
-            self.last_update_id.replace(last_update_id as usize);
-        };
-
         Ok(updates)
     }
+
+    fn commit(&mut self, update_id: UpdateId) {
+        self.offset.replace((update_id + 1) as usize);
+    }
 }
@@ -0,0 +1,89 @@
+use crate::connector::Connector;
+use api::{basic_types::UpdateId, proto::CommonUpdate};
+use async_trait::async_trait;
+use eyre::{eyre, WrapErr};
+use log::info;
+use std::path::Path;
+
+/// Feeds the updates of a journal instead of fetching them from Telegram,
+/// the journal has an update per line as Telegram sends it to the webhook
+pub struct ReplayConnector {
+    updates: Vec<CommonUpdate>,
+    last_id: UpdateId,
+    committed: UpdateId,
+}
+
+impl ReplayConnector {
+    pub fn open(journal: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(journal)
+            .wrap_err_with(|| format!("failed to read journal {journal:?}"))?;
+        Self::parse(&contents).wrap_err_with(|| format!("broken journal {journal:?}"))
+    }
+
+    fn parse(journal: &str) -> eyre::Result<Self> {
+        let mut updates = vec![];
+        for (index, line) in journal.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let update = serde_json::from_str::<CommonUpdate>(line)
+                .map_err(|err| eyre!("line {}, {err}", index + 1))?;
+            updates.push(update);
+        }
+        updates.sort_by_key(|update| update.id);
+        Ok(Self {
+            last_id: updates.last().map(|update| update.id).unwrap_or_default(),
+            updates,
+            committed: 0,
+        })
+    }
+}
+
+#[async_trait]
+impl Connector for ReplayConnector {
+    async fn on_startup(&mut self) -> eyre::Result<()> {
+        info!("replaying {} updates", self.updates.len());
+        Ok(())
+    }
+
+    async fn fetch_updates(&mut self) -> eyre::Result<Vec<CommonUpdate>> {
+        Ok(std::mem::take(&mut self.updates))
+    }
+
+    fn commit(&mut self, update_id: UpdateId) {
+        self.committed = self.committed.max(update_id);
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.updates.is_empty() && self.committed >= self.last_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn journal_is_replayed_once() {
+        let journal = r#"
+            {"update_id": 12, "message": {"message_id": 2, "date": 0, "chat": {"id": 1, "type": "private"}, "text": "b"}}
+            {"update_id": 11, "message": {"message_id": 1, "date": 0, "chat": {"id": 1, "type": "private"}, "text": "a"}}
+        "#;
+        let mut connector = ReplayConnector::parse(journal).unwrap();
+        let ids = connector
+            .fetch_updates()
+            .await
+            .unwrap()
+            .iter()
+            .map(|update| update.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [11, 12]);
+        assert!(connector.fetch_updates().await.unwrap().is_empty());
+        connector.commit(11);
+        assert!(!connector.is_exhausted());
+        connector.commit(12);
+        assert!(connector.is_exhausted());
+
+        assert!(ReplayConnector::parse("{}").is_err());
+    }
+}
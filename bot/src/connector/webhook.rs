@@ -1,5 +1,6 @@
 use crate::connector::Connector;
 use api::{
+    basic_types::UpdateId,
     endpoints::{Empty, GetWebhookInfo, SetWebhook},
     proto::{CommonUpdate, InputFile, UpdateType},
     request::SetWebhookRequest,
@@ -13,27 +14,81 @@ use axum_server::tls_rustls::RustlsConfig;
 use compact_str::{CompactString, ToCompactString};
 use eyre::{bail, ensure, eyre};
 use http::StatusCode;
-use log::{debug, trace};
+use log::{debug, error, info, trace, warn};
+use rcgen::{Certificate, CertificateParams, DnType, SanType};
+use reqwest::Url;
 use std::{
-    net::{Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    fs::{File, OpenOptions},
+    io::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, Mutex},
 };
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
 
 pub struct WebhookConnector {
     config: WebhookConnectorConfig,
     token: CompactString,
-    rx: Option<UnboundedReceiver<eyre::Result<CommonUpdate>>>,
+    rx: Option<Receiver<CommonUpdate>>,
+    queue: Option<SharedQueue>,
+    /// Updates queued before the restart, fetched first
+    pending: Vec<CommonUpdate>,
 }
 
-#[derive(Default)]
 pub struct WebhookConnectorConfig {
     pub https_url: CompactString,
     pub ip_address: Option<CompactString>,
     pub drop_pending_updates: bool,
     pub max_connections: Option<i32>,
     pub allowed_updates: Vec<UpdateType>,
+    /// Address to listen on, `ip_address` or localhost if not set
+    pub listen_address: Option<CompactString>,
+    pub port: u16,
+    /// Serve https with the certificate from `cert_dir`,
+    /// turn off when a reverse proxy terminates TLS
+    pub tls: bool,
+    /// Upload the certificate from `cert_dir` to Telegram,
+    /// turn off when the certificate is signed by a trusted CA
+    pub upload_certificate: bool,
+    /// Directory with `cert.pem` and `key.pem`
+    pub cert_dir: PathBuf,
+    /// Updates not yet fetched by the bot, Telegram is answered with 429 when it's full
+    pub queue_size: usize,
+    /// Max number of updates returned by a single fetch
+    pub batch_limit: usize,
+    /// File the updates are written to before Telegram is answered, see [`UpdateQueue`]
+    pub queue_path: PathBuf,
+}
+
+impl Default for WebhookConnectorConfig {
+    fn default() -> Self {
+        Self {
+            https_url: Default::default(),
+            ip_address: None,
+            drop_pending_updates: false,
+            max_connections: None,
+            allowed_updates: vec![],
+            listen_address: None,
+            port: 443,
+            tls: true,
+            upload_certificate: true,
+            cert_dir: PathBuf::from("self_signed_certs"),
+            queue_size: 1024,
+            batch_limit: 100,
+            queue_path: PathBuf::from("webhook.queue"),
+        }
+    }
+}
+
+impl WebhookConnectorConfig {
+    fn cert_path(&self) -> PathBuf {
+        self.cert_dir.join("cert.pem")
+    }
+
+    fn key_path(&self) -> PathBuf {
+        self.cert_dir.join("key.pem")
+    }
 }
 
 impl WebhookConnector {
@@ -42,43 +97,78 @@ impl WebhookConnector {
             token: token.into(),
             config,
             rx: None,
+            queue: None,
+            pending: vec![],
         }
     }
-}
 
-#[async_trait]
-impl Connector for WebhookConnector {
-    async fn on_startup(&mut self) -> eyre::Result<()> {
+    /// Generates a self-signed certificate for the webhook host
+    /// unless both certificate and key files are present already
+    fn generate_cert_if_missing(&self) -> eyre::Result<()> {
+        let (cert_path, key_path) = (self.config.cert_path(), self.config.key_path());
+        if cert_path.exists() && key_path.exists() {
+            return Ok(());
+        }
+        ensure!(
+            self.config.tls,
+            "certificate to upload not found, path = {cert_path:?}"
+        );
+
+        let url = Url::parse(&self.config.https_url)?;
+        let host = url
+            .host_str()
+            .ok_or(eyre!("no host in webhook url '{}'", self.config.https_url))?;
+
+        let mut params = CertificateParams::new(vec![]);
+        // Telegram checks an IP address against the IP SANs only
+        let ip = host.trim_start_matches('[').trim_end_matches(']');
+        params.subject_alt_names.push(match IpAddr::from_str(ip) {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(host.to_string()),
+        });
+        params.distinguished_name.push(DnType::CommonName, host);
+        let cert = Certificate::from_params(params)?;
+
+        std::fs::create_dir_all(&self.config.cert_dir)?;
+        write_pem(&cert_path, &cert.serialize_pem()?)?;
+        write_pem(&key_path, &cert.serialize_private_key_pem())?;
+
+        info!("self-signed certificate for '{host}' generated, path = {cert_path:?}");
+        Ok(())
+    }
+
+    /// Starts the server that queues the updates Telegram posts
+    async fn listen(&mut self) -> eyre::Result<()> {
         let addr = SocketAddr::new(
-            match self.config.ip_address.as_ref() {
+            match self
+                .config
+                .listen_address
+                .as_ref()
+                .or(self.config.ip_address.as_ref())
+            {
                 None => Ipv4Addr::new(127, 0, 0, 1),
-                Some(ip) => Ipv4Addr::from_str(&ip)?,
+                Some(ip) => Ipv4Addr::from_str(ip)?,
             }
             .into(),
-            443,
+            self.config.port,
         );
 
-        let work_dir = std::env::var("WORK_DIR").expect("WORK_DIR not set");
-        let cert_path = PathBuf::from(&work_dir)
-            .join("self_signed_certs")
-            .join("cert.pem");
-        let certificate = Some(InputFile::FilePath(
-            cert_path
-                .to_str()
-                .ok_or(eyre!("failed to get cert path"))?
-                .to_compact_string(),
-        ));
-
-        let (tx, rx) = unbounded_channel();
+        let (queue, pending) = UpdateQueue::open(&self.config.queue_path)?;
+        if !pending.is_empty() {
+            info!(
+                "{} updates queued before the restart are handled first",
+                pending.len()
+            );
+        }
+        let queue = Arc::new(Mutex::new(queue));
+        self.pending = pending;
+        self.queue.replace(queue.clone());
+        let (tx, rx) = channel(self.config.queue_size);
 
         let app = Router::new()
             .route(
                 "/",
-                post(move |Json(payload): Json<CommonUpdate>| async move {
-                    debug!("webhook update received: {:?}", payload);
-                    tx.send(Ok(payload)).expect("failed to send webhook update");
-                    StatusCode::OK
-                }),
+                post(move |Json(payload): Json<serde_json::Value>| receive(tx, queue, payload)),
             )
             .route(
                 "/health-check",
@@ -90,19 +180,146 @@ impl Connector for WebhookConnector {
 
         self.rx.replace(rx);
 
-        let config = RustlsConfig::from_pem_file(
-            cert_path,
-            PathBuf::from(work_dir)
-                .join("self_signed_certs")
-                .join("key.pem"),
-        )
-        .await?;
+        if self.config.tls {
+            let config =
+                RustlsConfig::from_pem_file(self.config.cert_path(), self.config.key_path())
+                    .await?;
+            tokio::spawn(axum_server::bind_rustls(addr, config).serve(app.into_make_service()));
+            debug!("jab is listening on https://{addr:?}...");
+        } else {
+            tokio::spawn(axum_server::bind(addr).serve(app.into_make_service()));
+            debug!("jab is listening on http://{addr:?}...");
+        }
+        Ok(())
+    }
+}
 
-        let srv = axum_server::bind_rustls(addr, config).serve(app.into_make_service());
+fn write_pem(path: &Path, pem: &str) -> eyre::Result<()> {
+    std::fs::write(path, pem).map_err(|err| eyre!("failed to write {path:?}, {err}"))
+}
 
-        tokio::spawn(srv);
+/// Telegram considers an update delivered once it's answered with 200, so every update
+/// is written to the file first, a line of JSON per update as in a journal;
+/// the file is emptied once the bot has handled all of them
+struct UpdateQueue {
+    file: File,
+    /// Last update id written to the file
+    last_id: UpdateId,
+}
 
-        debug!("jab is listening on {addr:?}...");
+type SharedQueue = Arc<Mutex<UpdateQueue>>;
+
+impl UpdateQueue {
+    /// Returns the updates left in the file as well, a line broken by a crash is skipped
+    fn open(path: &Path) -> eyre::Result<(Self, Vec<CommonUpdate>)> {
+        let mut updates = vec![];
+        if path.exists() {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| eyre!("failed to read {path:?}, {err}"))?;
+            for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                match serde_json::from_str::<CommonUpdate>(line) {
+                    Ok(update) => updates.push(update),
+                    Err(err) => warn!("skipping broken update in {path:?}, {err}"),
+                }
+            }
+            updates.sort_by_key(|update: &CommonUpdate| update.id);
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| eyre!("failed to open {path:?}, {err}"))?;
+        let last_id = updates.last().map(|update| update.id).unwrap_or_default();
+        Ok((Self { file, last_id }, updates))
+    }
+
+    fn push(&mut self, id: UpdateId, line: &str) -> std::io::Result<()> {
+        writeln!(self.file, "{line}")?;
+        self.file.sync_data()?;
+        self.last_id = self.last_id.max(id);
+        Ok(())
+    }
+
+    fn commit(&mut self, update_id: UpdateId) -> std::io::Result<()> {
+        if self.last_id != 0 && update_id >= self.last_id {
+            self.file.set_len(0)?;
+            self.file.sync_data()?;
+            self.last_id = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Answers Telegram with 200 only once the update is in the queue file,
+/// and with 429 if the bot is that far behind
+async fn receive(
+    tx: Sender<CommonUpdate>,
+    queue: SharedQueue,
+    payload: serde_json::Value,
+) -> StatusCode {
+    let update = match serde_json::from_value::<CommonUpdate>(payload.clone()) {
+        Ok(update) => update,
+        Err(err) => {
+            error!("failed to parse webhook update, {err}");
+            return StatusCode::UNPROCESSABLE_ENTITY;
+        }
+    };
+    debug!("webhook update received: {:?}", update);
+    let permit = match tx.try_reserve() {
+        Ok(permit) => permit,
+        Err(TrySendError::Full(())) => {
+            warn!("update queue is full, update #{} postponed", update.id);
+            return StatusCode::TOO_MANY_REQUESTS;
+        }
+        Err(TrySendError::Closed(())) => {
+            error!("update queue is closed, update #{} rejected", update.id);
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+    };
+    let id = update.id;
+    let written = tokio::task::spawn_blocking(move || {
+        queue
+            .lock()
+            .expect("webhook queue lock poisoned")
+            .push(id, &payload.to_string())
+    })
+    .await;
+    match written {
+        Ok(Ok(())) => {
+            permit.send(update);
+            StatusCode::OK
+        }
+        Ok(Err(err)) => {
+            error!("failed to queue update #{id}, {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        Err(err) => {
+            error!("failed to queue update #{id}, {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[async_trait]
+impl Connector for WebhookConnector {
+    async fn on_startup(&mut self) -> eyre::Result<()> {
+        if self.config.tls || self.config.upload_certificate {
+            self.generate_cert_if_missing()?;
+        }
+
+        let certificate = if self.config.upload_certificate {
+            Some(InputFile::FilePath(
+                self.config
+                    .cert_path()
+                    .to_str()
+                    .ok_or(eyre!("failed to get cert path"))?
+                    .to_compact_string(),
+            ))
+        } else {
+            None
+        };
+
+        self.listen().await?;
 
         let request = SetWebhookRequest {
             url: self.config.https_url.clone(),
@@ -133,8 +350,10 @@ impl Connector for WebhookConnector {
         debug!("webhook info: {info:?}");
 
         ensure!(
+            info.has_custom_certificate == self.config.upload_certificate,
+            "webhook custom certificate = {}, expected {}",
             info.has_custom_certificate,
-            "webhook set without certificate"
+            self.config.upload_certificate
         );
         ensure!(info.url == self.config.https_url, "wrong webhook https url");
         ensure!(
@@ -146,10 +365,177 @@ impl Connector for WebhookConnector {
     }
 
     async fn fetch_updates(&mut self) -> eyre::Result<Vec<CommonUpdate>> {
+        if !self.pending.is_empty() {
+            let count = self.pending.len().min(self.config.batch_limit);
+            return Ok(self.pending.drain(..count).collect());
+        }
         let Some(rx) = self.rx.as_mut() else {
             bail!("uninitialized connector")
         };
-        let update = rx.recv().await.expect("update channel died")?;
-        Ok(vec![update])
+        let Some(update) = rx.recv().await else {
+            bail!("update channel died");
+        };
+        let mut updates = vec![update];
+        while updates.len() < self.config.batch_limit {
+            match rx.try_recv() {
+                Ok(update) => updates.push(update),
+                Err(_) => break,
+            }
+        }
+        Ok(updates)
+    }
+
+    /// The queue file is kept until every update in it is handled
+    fn commit(&mut self, update_id: UpdateId) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+        let mut queue = queue.lock().expect("webhook queue lock poisoned");
+        if let Err(err) = queue.commit(update_id) {
+            error!("failed to empty the webhook update queue, {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    fn update(id: UpdateId) -> String {
+        format!(
+            r#"{{"update_id": {id}, "message": {{"message_id": {id}, "date": 0, "chat": {{"id": 1, "type": "private"}}, "text": "a"}}}}"#
+        )
+    }
+
+    fn config(dir: &TempDir, tls: bool) -> WebhookConnectorConfig {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        WebhookConnectorConfig {
+            https_url: format!("https://127.0.0.1:{port}/jab3").into(),
+            port,
+            tls,
+            upload_certificate: false,
+            cert_dir: dir.join("certs"),
+            queue_path: dir.join("jab.data.queue"),
+            ..Default::default()
+        }
+    }
+
+    /// Starts listening and waits until the server answers
+    async fn start(config: WebhookConnectorConfig, client: &reqwest::Client) -> WebhookConnector {
+        let scheme = if config.tls { "https" } else { "http" };
+        let url = format!("{scheme}://127.0.0.1:{}/health-check", config.port);
+        let mut connector = WebhookConnector::with_config("token", config);
+        connector.listen().await.unwrap();
+        for _ in 0..100 {
+            if client.get(&url).send().await.is_ok() {
+                return connector;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("webhook server is not listening at {url}");
+    }
+
+    async fn post(connector: &WebhookConnector, id: UpdateId) -> u16 {
+        reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}/", connector.config.port))
+            .header("content-type", "application/json")
+            .body(update(id))
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    async fn fetch_ids(connector: &mut WebhookConnector) -> Vec<UpdateId> {
+        let updates = connector.fetch_updates().await.unwrap();
+        updates.iter().map(|update| update.id).collect()
+    }
+
+    #[tokio::test]
+    async fn missing_certificate_is_generated() {
+        let dir = TempDir::new("webhook_cert");
+        let config = config(&dir, true);
+        let connector = WebhookConnector::with_config("token", config);
+        connector.generate_cert_if_missing().unwrap();
+        let cert = std::fs::read(connector.config.cert_path()).unwrap();
+        connector.generate_cert_if_missing().unwrap();
+        assert_eq!(std::fs::read(connector.config.cert_path()).unwrap(), cert);
+
+        // the certificate is valid for the IP address the webhook url has
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let connector = start(connector.config, &client).await;
+        let url = format!("https://127.0.0.1:{}/health-check", connector.config.port);
+        let status = client.get(url).send().await.unwrap().status();
+        assert_eq!(status.as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn plain_http_is_served() {
+        let dir = TempDir::new("webhook_http");
+        let mut connector = start(config(&dir, false), &reqwest::Client::new()).await;
+        assert_eq!(post(&connector, 1).await, 200);
+        assert_eq!(fetch_ids(&mut connector).await, [1]);
+        assert!(!dir.join("certs").exists());
+    }
+
+    #[tokio::test]
+    async fn full_queue_is_answered_with_429() {
+        let dir = TempDir::new("webhook_full");
+        let config = WebhookConnectorConfig {
+            queue_size: 1,
+            ..config(&dir, false)
+        };
+        let mut connector = start(config, &reqwest::Client::new()).await;
+        assert_eq!(post(&connector, 1).await, 200);
+        assert_eq!(post(&connector, 2).await, 429);
+        assert_eq!(fetch_ids(&mut connector).await, [1]);
+        assert_eq!(post(&connector, 2).await, 200);
+        assert_eq!(fetch_ids(&mut connector).await, [2]);
+    }
+
+    #[tokio::test]
+    async fn updates_are_fetched_in_batches() {
+        let dir = TempDir::new("webhook_batch");
+        let config = WebhookConnectorConfig {
+            batch_limit: 2,
+            ..config(&dir, false)
+        };
+        let mut connector = start(config, &reqwest::Client::new()).await;
+        for id in 1..=3 {
+            assert_eq!(post(&connector, id).await, 200);
+        }
+        assert_eq!(fetch_ids(&mut connector).await, [1, 2]);
+        assert_eq!(fetch_ids(&mut connector).await, [3]);
+    }
+
+    #[test]
+    fn queued_updates_survive_restart() {
+        let dir = TempDir::new("webhook_queue");
+        let path = dir.join("data.queue");
+
+        let (mut queue, pending) = UpdateQueue::open(&path).unwrap();
+        assert!(pending.is_empty());
+        queue.push(2, &update(2)).unwrap();
+        queue.push(1, &update(1)).unwrap();
+        queue.commit(1).unwrap();
+        drop(queue);
+
+        let (mut queue, pending) = UpdateQueue::open(&path).unwrap();
+        let ids = pending.iter().map(|update| update.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2]);
+        queue.commit(2).unwrap();
+        drop(queue);
+
+        let (_, pending) = UpdateQueue::open(&path).unwrap();
+        assert!(pending.is_empty());
     }
 }
@@ -3,3 +3,4 @@ pub mod communicator;
 pub mod connector;
 pub mod module;
 pub mod persistence;
+pub mod storage;
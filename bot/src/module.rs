@@ -1,15 +1,105 @@
-use crate::{bot::command::BotCommandInfo, communicator::Communicate, persistence::Persistence};
-use api::proto::Message;
+use crate::{
+    bot::{chat_settings::SettingSpec, command::BotCommandInfo, registry::CommandSpec},
+    communicator::Communicate,
+    persistence::Persistence,
+};
+use api::proto::{
+    CallbackQuery, ChatJoinRequest, ChatMemberUpdated, InlineQuery, Message, PollAnswer,
+};
 use async_trait::async_trait;
 
+/// Modules are shared between chats handled concurrently,
+/// so any mutable state has to be behind a lock
+///
+/// Every hook but `commands` and `try_execute_command` does nothing by default
 #[async_trait]
-pub trait Module {
+pub trait Module: Send + Sync {
+    /// Commands routed to `try_execute_command`, registered once when the module is added
+    fn commands(&self) -> Vec<CommandSpec>;
+
+    /// Settings chat admins may change for their chat,
+    /// read through [`BotCommandInfo::settings`]
+    fn settings(&self) -> Vec<SettingSpec> {
+        vec![]
+    }
+
+    /// Called for a command of this module only, `cmd` holds the command name
+    /// even if it was called by an alias
     async fn try_execute_command(
-        &mut self,
+        &self,
         comm: &dyn Communicate,
         cmd: &BotCommandInfo,
         message: &Message,
     ) -> eyre::Result<()>;
+
+    /// Called for messages that are not commands of any module
+    async fn on_message(&self, _comm: &dyn Communicate, _message: &Message) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the data is loaded, before the first update is handled
+    async fn on_startup(&self, _comm: &dyn Communicate) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the last update is handled, before the data is saved
+    async fn on_shutdown(&self, _comm: &dyn Communicate) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Called for edited messages and edited channel posts
+    async fn on_edited_message(
+        &self,
+        _comm: &dyn Communicate,
+        _message: &Message,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn on_channel_post(&self, _comm: &dyn Communicate, _post: &Message) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn on_callback_query(
+        &self,
+        _comm: &dyn Communicate,
+        _query: &CallbackQuery,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn on_inline_query(
+        &self,
+        _comm: &dyn Communicate,
+        _query: &InlineQuery,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Called for member changes of both the bot itself and other users
+    async fn on_chat_member(
+        &self,
+        _comm: &dyn Communicate,
+        _update: &ChatMemberUpdated,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn on_join_request(
+        &self,
+        _comm: &dyn Communicate,
+        _request: &ChatJoinRequest,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn on_poll_answer(
+        &self,
+        _comm: &dyn Communicate,
+        _answer: &PollAnswer,
+    ) -> eyre::Result<()> {
+        Ok(())
+    }
 }
 
-pub trait PersistentModule: Module + Persistence + Send {}
+pub trait PersistentModule: Module + Persistence {}
@@ -1,3 +1,13 @@
+use crate::storage::ChatStorage;
+use bincode::{Decode, Encode};
+use eyre::bail;
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
 pub trait Persistence {
     type Input;
     type Output;
@@ -5,4 +15,156 @@ pub trait Persistence {
     fn serialize(&self) -> eyre::Result<Self::Output>;
 
     fn deserialize(&mut self, input: Self::Input) -> eyre::Result<()>;
+
+    /// The data as JSON for people to read and fix by hand
+    fn export(&self) -> eyre::Result<serde_json::Value>;
+
+    /// Replaces the data with the exported one
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()>;
+
+    /// Migrations of the data, the n-th one takes the data of version n to version n + 1,
+    /// so `serialize` writes the version equal to their count
+    fn migrations(&self) -> Vec<Migration<Self::Input>> {
+        vec![]
+    }
+
+    /// Writes the data kept under the chat keys instead of `serialize`,
+    /// both are saved in the same transaction
+    fn save_chats(&self, _storage: &mut ChatStorage) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// Reads the data kept under the chat keys, called after `deserialize`
+    fn load_chats(&mut self, _storage: &ChatStorage) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+/// Rewrites the data of one version in the layout of the next one
+pub type Migration<T> = fn(T) -> eyre::Result<T>;
+
+/// Tells the enveloped data from that saved before the versions, which is version 0
+const ENVELOPE_MAGIC: &[u8] = b"jabv";
+
+#[derive(Encode, Decode)]
+struct Envelope {
+    version: u32,
+    data: Vec<u8>,
+}
+
+/// Tags the data with the version it was written in
+pub(crate) fn seal(version: usize, data: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    let envelope = Envelope {
+        version: version.try_into()?,
+        data,
+    };
+    let mut bytes = ENVELOPE_MAGIC.to_vec();
+    bytes.extend(bincode::encode_to_vec(
+        envelope,
+        bincode::config::standard(),
+    )?);
+    Ok(bytes)
+}
+
+/// Brings the data up to the current version, refuses data of the versions yet unknown
+pub(crate) fn unseal(bytes: Vec<u8>, migrations: &[Migration<Vec<u8>>]) -> eyre::Result<Vec<u8>> {
+    let (version, mut data) = match bytes.strip_prefix(ENVELOPE_MAGIC) {
+        Some(enveloped) => {
+            let envelope =
+                bincode::decode_from_slice::<Envelope, _>(enveloped, bincode::config::standard())?
+                    .0;
+            (envelope.version as usize, envelope.data)
+        }
+        None => (0, bytes),
+    };
+    if version > migrations.len() {
+        bail!(
+            "data version {version} is newer than {}, the latest one known",
+            migrations.len()
+        );
+    }
+    for (from, migrate) in migrations.iter().enumerate().skip(version) {
+        data = migrate(data).map_err(|err| err.wrap_err(format!("version {from} migration")))?;
+    }
+    Ok(data)
+}
+
+/// Replaces the file contents so that a crash leaves either the old or the new version,
+/// never a mix of both
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// `jab.data.2` for the second newest backup of `jab.data`
+pub(crate) fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut backup = OsString::from(path.as_os_str());
+    backup.push(format!(".{index}"));
+    PathBuf::from(backup)
+}
+
+/// Shifts the backups by one dropping the oldest and copies the file as the newest,
+/// the file itself stays in place
+pub(crate) fn rotate_backups(path: &Path, count: usize) -> std::io::Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for index in (1..count).rev() {
+        let older = backup_path(path, index);
+        if older.exists() {
+            std::fs::rename(&older, backup_path(path, index + 1))?;
+        }
+    }
+    std::fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    #[test]
+    fn backups_are_rotated() {
+        let dir = TempDir::new("backups");
+        let path = dir.join("jab.data");
+        for version in ["1", "2", "3", "4"] {
+            rotate_backups(&path, 2).unwrap();
+            write_atomically(&path, version.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "4");
+        assert_eq!(read(backup_path(&path, 1)), "3");
+        assert_eq!(read(backup_path(&path, 2)), "2");
+        assert!(!backup_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn data_is_migrated_to_the_latest_version() {
+        let migrations: [Migration<Vec<u8>>; 2] = [
+            |mut data| {
+                data.push(1);
+                Ok(data)
+            },
+            |mut data| {
+                data.push(2);
+                Ok(data)
+            },
+        ];
+        // data saved before the versions is version 0
+        assert_eq!(unseal(vec![0], &migrations).unwrap(), [0, 1, 2]);
+        let sealed = seal(1, vec![0, 1]).unwrap();
+        assert_eq!(unseal(sealed, &migrations).unwrap(), [0, 1, 2]);
+        let sealed = seal(2, vec![0, 1, 2]).unwrap();
+        assert_eq!(unseal(sealed.clone(), &migrations).unwrap(), [0, 1, 2]);
+        assert!(unseal(sealed, &migrations[..1]).is_err());
+    }
 }
@@ -0,0 +1,309 @@
+use crate::{
+    persistence::{backup_path, rotate_backups, write_atomically},
+    storage::Storage,
+};
+use api::basic_types::UpdateId;
+use eyre::{bail, ensure, WrapErr};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+type Namespaces = BTreeMap<String, BTreeMap<String, Vec<u8>>>;
+
+/// Tells the file from one written before the storage backends
+const MAGIC: &[u8] = b"jab3kv";
+
+/// Backups are taken on the first flush after start and at most this often afterwards,
+/// so that autosaves do not push the older states out
+const ROTATION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Keeps everything in memory and rewrites the whole file on flush
+pub struct FileStorage {
+    path: PathBuf,
+    backups: usize,
+    namespaces: Namespaces,
+    dirty: bool,
+    last_rotated: Option<Instant>,
+    read_only: bool,
+}
+
+impl FileStorage {
+    /// Falls back to the backups, newest first, if the file is missing or broken,
+    /// a broken file is moved aside so that it is neither overwritten nor rotated into the backups;
+    /// starts empty only if there is none of them, fails if none of them loads
+    pub fn open(path: impl Into<PathBuf>, backups: usize) -> eyre::Result<Self> {
+        let path = path.into();
+        let mut found = path.exists();
+        let mut loaded = read_namespaces(&path);
+        let broken = loaded.is_err() && found;
+        for index in 1..=backups {
+            let Err(err) = &loaded else {
+                break;
+            };
+            let backup = backup_path(&path, index);
+            if !backup.exists() {
+                continue;
+            }
+            found = true;
+            warn!("failed to load {path:?}, {err}, trying backup {backup:?}");
+            loaded = read_namespaces(&backup);
+        }
+        let namespaces = match loaded {
+            Ok(namespaces) => namespaces,
+            Err(err) if found => {
+                return Err(err.wrap_err(format!(
+                    "failed to load {path:?} or its backups, move them away to start with no data"
+                )))
+            }
+            Err(_) => Default::default(),
+        };
+        if broken {
+            let aside = broken_path(&path);
+            std::fs::rename(&path, &aside)
+                .wrap_err_with(|| format!("failed to move broken {path:?} aside"))?;
+            warn!("broken {path:?} is moved to {aside:?}, the data is loaded from a backup");
+        }
+        Ok(Self {
+            path,
+            backups,
+            namespaces,
+            dirty: false,
+            last_rotated: None,
+            read_only: false,
+        })
+    }
+
+    /// Loads the file only, neither moving it aside if it's broken nor falling back to the backups,
+    /// and never writes it
+    pub fn open_read_only(path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let path = path.into();
+        let namespaces = if path.exists() {
+            read_namespaces(&path).wrap_err_with(|| format!("failed to load {path:?}"))?
+        } else {
+            Default::default()
+        };
+        Ok(Self {
+            path,
+            backups: 0,
+            namespaces,
+            dirty: false,
+            last_rotated: None,
+            read_only: true,
+        })
+    }
+}
+
+/// `jab.data.broken` for `jab.data`
+fn broken_path(path: &Path) -> PathBuf {
+    let mut broken = path.as_os_str().to_owned();
+    broken.push(".broken");
+    PathBuf::from(broken)
+}
+
+fn read_namespaces(path: &Path) -> eyre::Result<Namespaces> {
+    let bytes = std::fs::read(path)?;
+    let Some(bytes) = bytes.strip_prefix(MAGIC) else {
+        return read_legacy(&bytes);
+    };
+    Ok(bincode::decode_from_slice(bytes, bincode::config::standard())?.0)
+}
+
+/// Module data by name and the last update id, as the bot saved before the storage backends
+fn read_legacy(bytes: &[u8]) -> eyre::Result<Namespaces> {
+    let ((modules, last_update_id), read) = bincode::decode_from_slice::<
+        (HashMap<String, Vec<u8>>, UpdateId),
+        _,
+    >(bytes, bincode::config::standard())?;
+    if read != bytes.len() {
+        bail!("{} bytes left over", bytes.len() - read);
+    }
+    let last_update_id = bincode::encode_to_vec(last_update_id, bincode::config::standard())?;
+    Ok(BTreeMap::from([
+        ("modules".to_string(), modules.into_iter().collect()),
+        (
+            "bot".to_string(),
+            BTreeMap::from([("last_update_id".to_string(), last_update_id)]),
+        ),
+    ]))
+}
+
+impl Storage for FileStorage {
+    fn get(&self, namespace: &str, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        Ok(self
+            .namespaces
+            .get(namespace)
+            .and_then(|keys| keys.get(key))
+            .cloned())
+    }
+
+    /// Putting the value the key has already leaves the storage clean
+    fn put(&mut self, namespace: &str, key: &str, value: Vec<u8>) -> eyre::Result<()> {
+        let keys = self.namespaces.entry(namespace.into()).or_default();
+        if keys.get(key) != Some(&value) {
+            keys.insert(key.into(), value);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, namespace: &str, key: &str) -> eyre::Result<()> {
+        if let Some(keys) = self.namespaces.get_mut(namespace) {
+            self.dirty |= keys.remove(key).is_some();
+            if keys.is_empty() {
+                self.namespaces.remove(namespace);
+            }
+        }
+        Ok(())
+    }
+
+    fn keys(&self, namespace: &str) -> eyre::Result<Vec<String>> {
+        Ok(self
+            .namespaces
+            .get(namespace)
+            .map(|keys| keys.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn namespaces(&self) -> eyre::Result<Vec<String>> {
+        Ok(self.namespaces.keys().cloned().collect())
+    }
+
+    fn flush(&mut self) -> eyre::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        ensure!(!self.read_only, "{:?} is opened read-only", self.path);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::encode_to_vec(
+            &self.namespaces,
+            bincode::config::standard(),
+        )?);
+        if self
+            .last_rotated
+            .is_none_or(|rotated| rotated.elapsed() >= ROTATION_INTERVAL)
+        {
+            rotate_backups(&self.path, self.backups)?;
+            self.last_rotated = Some(Instant::now());
+        }
+        write_atomically(&self.path, &bytes)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// The data is restored from a copy taken before `f` if it fails
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Storage) -> eyre::Result<()>,
+    ) -> eyre::Result<()> {
+        let (namespaces, dirty) = (self.namespaces.clone(), self.dirty);
+        f(self).inspect_err(|_| {
+            self.namespaces = namespaces;
+            self.dirty = dirty;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{check_storage, TempDir};
+
+    #[test]
+    fn file_storage() {
+        let dir = TempDir::new("file_storage");
+        let path = dir.join("jab.data");
+
+        let legacy = (
+            HashMap::from([("imager".to_string(), vec![7u8])]),
+            42 as UpdateId,
+        );
+        std::fs::write(
+            &path,
+            bincode::encode_to_vec(legacy, bincode::config::standard()).unwrap(),
+        )
+        .unwrap();
+        let mut storage = FileStorage::open(&path, 1).unwrap();
+        assert_eq!(storage.get("modules", "imager").unwrap(), Some(vec![7]));
+        storage.delete("modules", "imager").unwrap();
+        storage.delete("bot", "last_update_id").unwrap();
+
+        check_storage(&mut storage);
+        let reopened = FileStorage::open(&path, 1).unwrap();
+        assert_eq!(reopened.get("imager", "a").unwrap(), Some(vec![4]));
+    }
+
+    #[test]
+    fn backups_keep_the_state_before_start() {
+        let dir = TempDir::new("file_backups");
+        let path = dir.join("jab.data");
+
+        let mut storage = FileStorage::open(&path, 2).unwrap();
+        storage.put("imager", "a", vec![1]).unwrap();
+        storage.flush().unwrap();
+
+        let mut storage = FileStorage::open(&path, 2).unwrap();
+        for value in 2..5 {
+            storage.put("imager", "a", vec![value]).unwrap();
+            storage.flush().unwrap();
+        }
+        let backup = FileStorage::open(backup_path(&path, 1), 0).unwrap();
+        assert_eq!(backup.get("imager", "a").unwrap(), Some(vec![1]));
+        assert!(!backup_path(&path, 2).exists());
+    }
+
+    #[test]
+    fn equal_value_is_not_written() {
+        let dir = TempDir::new("file_equal");
+        let path = dir.join("jab.data");
+
+        let mut storage = FileStorage::open(&path, 0).unwrap();
+        storage.put("imager", "a", vec![1]).unwrap();
+        storage.flush().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        storage.put("imager", "a", vec![1]).unwrap();
+        storage.flush().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn broken_file_is_not_overwritten() {
+        let dir = TempDir::new("file_broken");
+        let path = dir.join("jab.data");
+
+        std::fs::write(&path, b"broken").unwrap();
+        assert!(FileStorage::open(&path, 1).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"broken");
+
+        let mut backup = FileStorage::open(backup_path(&path, 1), 0).unwrap();
+        backup.put("imager", "a", vec![1]).unwrap();
+        backup.flush().unwrap();
+        let storage = FileStorage::open(&path, 1).unwrap();
+        assert_eq!(storage.get("imager", "a").unwrap(), Some(vec![1]));
+        assert!(!path.exists());
+        assert_eq!(std::fs::read(broken_path(&path)).unwrap(), b"broken");
+    }
+
+    #[test]
+    fn read_only_leaves_the_files() {
+        let dir = TempDir::new("file_read_only");
+        let path = dir.join("jab.data");
+
+        let mut backup = FileStorage::open(backup_path(&path, 1), 0).unwrap();
+        backup.put("imager", "a", vec![1]).unwrap();
+        backup.flush().unwrap();
+        std::fs::write(&path, b"broken").unwrap();
+        assert!(FileStorage::open_read_only(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"broken");
+        assert!(!broken_path(&path).exists());
+
+        std::fs::remove_file(&path).unwrap();
+        let mut storage = FileStorage::open_read_only(&path).unwrap();
+        assert_eq!(storage.get("imager", "a").unwrap(), None);
+        storage.put("imager", "a", vec![2]).unwrap();
+        assert!(storage.flush().is_err());
+        assert!(!path.exists());
+    }
+}
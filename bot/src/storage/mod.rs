@@ -0,0 +1,175 @@
+use api::basic_types::ChatIntId;
+use eyre::eyre;
+use serde::Deserialize;
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+pub mod file;
+pub mod sqlite;
+
+/// Key-value storage of the bot data, keys are grouped in namespaces,
+/// e.g. one per module or per module and chat
+pub trait Storage: Send {
+    fn get(&self, namespace: &str, key: &str) -> eyre::Result<Option<Vec<u8>>>;
+
+    fn put(&mut self, namespace: &str, key: &str, value: Vec<u8>) -> eyre::Result<()>;
+
+    fn delete(&mut self, namespace: &str, key: &str) -> eyre::Result<()>;
+
+    /// Keys of the namespace in ascending order
+    fn keys(&self, namespace: &str) -> eyre::Result<Vec<String>>;
+
+    /// Namespaces having any keys in ascending order
+    fn namespaces(&self) -> eyre::Result<Vec<String>>;
+
+    /// Makes the changes durable, some backends write every change at once
+    fn flush(&mut self) -> eyre::Result<()>;
+
+    /// Applies either all the changes `f` makes or none of them if it fails
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Storage) -> eyre::Result<()>,
+    ) -> eyre::Result<()>;
+}
+
+/// Storage the bot data is kept in, shared by the bot tasks
+pub type SharedStorage = Arc<Mutex<dyn Storage>>;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// A single file rewritten as a whole on every save
+    #[default]
+    File,
+    /// SQLite database updated key by key
+    Sqlite,
+}
+
+impl FromStr for StorageBackend {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(Self::File),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(eyre!("unknown storage '{s}', file or sqlite expected")),
+        }
+    }
+}
+
+/// Namespace of the module data for a single chat
+pub fn chat_namespace(module: &str, chat_id: ChatIntId) -> String {
+    format!("{module}/{chat_id}")
+}
+
+/// Storage of a single module, which keeps its data of each chat under the chat keys,
+/// so that the chats are written one by one instead of all the data at once
+pub struct ChatStorage<'a> {
+    storage: &'a mut dyn Storage,
+    module: &'a str,
+}
+
+impl<'a> ChatStorage<'a> {
+    pub fn new(storage: &'a mut dyn Storage, module: &'a str) -> Self {
+        Self { storage, module }
+    }
+
+    pub fn get(&self, chat_id: ChatIntId, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        self.storage.get(&chat_namespace(self.module, chat_id), key)
+    }
+
+    pub fn put(&mut self, chat_id: ChatIntId, key: &str, value: Vec<u8>) -> eyre::Result<()> {
+        self.storage
+            .put(&chat_namespace(self.module, chat_id), key, value)
+    }
+
+    pub fn delete(&mut self, chat_id: ChatIntId, key: &str) -> eyre::Result<()> {
+        self.storage
+            .delete(&chat_namespace(self.module, chat_id), key)
+    }
+
+    /// Chats having any keys
+    pub fn chats(&self) -> eyre::Result<Vec<ChatIntId>> {
+        let prefix = format!("{}/", self.module);
+        Ok(self
+            .storage
+            .namespaces()?
+            .iter()
+            .filter_map(|namespace| namespace.strip_prefix(&prefix)?.parse().ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        ops::Deref,
+        path::{Path, PathBuf},
+    };
+
+    /// Directory in the system temp dir, removed on drop even if the test panics
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("jab_{name}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Checks the behaviour every backend has to have
+    pub fn check_storage(storage: &mut dyn Storage) {
+        storage.put("imager", "b", vec![2]).unwrap();
+        storage.put("imager", "a", vec![1]).unwrap();
+        storage
+            .put(&chat_namespace("imager", -100), "a", vec![3])
+            .unwrap();
+        storage.put("imager", "a", vec![4]).unwrap();
+        storage.flush().unwrap();
+
+        assert_eq!(storage.get("imager", "a").unwrap(), Some(vec![4]));
+        assert_eq!(storage.get("imager", "c").unwrap(), None);
+        assert_eq!(storage.keys("imager").unwrap(), ["a", "b"]);
+        assert_eq!(storage.namespaces().unwrap(), ["imager", "imager/-100"]);
+
+        let chats = ChatStorage::new(storage, "imager");
+        assert_eq!(chats.chats().unwrap(), [-100]);
+        assert_eq!(chats.get(-100, "a").unwrap(), Some(vec![3]));
+
+        storage.delete("imager/-100", "a").unwrap();
+        assert_eq!(storage.namespaces().unwrap(), ["imager"]);
+        assert!(storage.keys("imager/-100").unwrap().is_empty());
+
+        let rolled_back = storage.transaction(&mut |storage| {
+            storage.put("imager", "a", vec![5])?;
+            storage.delete("imager", "b")?;
+            eyre::bail!("failed in the middle")
+        });
+        assert!(rolled_back.is_err());
+        assert_eq!(storage.get("imager", "a").unwrap(), Some(vec![4]));
+        assert_eq!(storage.get("imager", "b").unwrap(), Some(vec![2]));
+        storage
+            .transaction(&mut |storage| storage.put("imager", "a", vec![6]))
+            .unwrap();
+        assert_eq!(storage.get("imager", "a").unwrap(), Some(vec![6]));
+    }
+}
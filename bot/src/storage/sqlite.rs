@@ -0,0 +1,139 @@
+use crate::storage::Storage;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::Path;
+
+/// Writes every change at once, so large data is updated key by key
+/// rather than rewritten as a whole
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Missing database is taken as empty rather than created
+    pub fn open_read_only(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::in_memory();
+        }
+        Ok(Self {
+            connection: Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?,
+        })
+    }
+
+    pub fn in_memory() -> eyre::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> eyre::Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS data (
+                namespace TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (namespace, key)
+            ) WITHOUT ROWID;",
+        )?;
+        Ok(Self { connection })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, namespace: &str, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        Ok(self
+            .connection
+            .prepare_cached("SELECT value FROM data WHERE namespace = ?1 AND key = ?2")?
+            .query_row(params![namespace, key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn put(&mut self, namespace: &str, key: &str, value: Vec<u8>) -> eyre::Result<()> {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO data (namespace, key, value) VALUES (?1, ?2, ?3)
+                ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value
+                WHERE value != excluded.value",
+            )?
+            .execute(params![namespace, key, value])?;
+        Ok(())
+    }
+
+    fn delete(&mut self, namespace: &str, key: &str) -> eyre::Result<()> {
+        self.connection
+            .prepare_cached("DELETE FROM data WHERE namespace = ?1 AND key = ?2")?
+            .execute(params![namespace, key])?;
+        Ok(())
+    }
+
+    fn keys(&self, namespace: &str) -> eyre::Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT key FROM data WHERE namespace = ?1 ORDER BY key")?;
+        let keys = statement
+            .query_map(params![namespace], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(keys)
+    }
+
+    fn namespaces(&self) -> eyre::Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT DISTINCT namespace FROM data ORDER BY namespace")?;
+        let namespaces = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(namespaces)
+    }
+
+    fn flush(&mut self) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn Storage) -> eyre::Result<()>,
+    ) -> eyre::Result<()> {
+        self.connection.execute_batch("BEGIN")?;
+        match f(self) {
+            Ok(()) => Ok(self.connection.execute_batch("COMMIT")?),
+            Err(err) => {
+                self.connection.execute_batch("ROLLBACK")?;
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{check_storage, TempDir};
+
+    #[test]
+    fn sqlite_storage() {
+        check_storage(&mut SqliteStorage::in_memory().unwrap());
+    }
+
+    #[test]
+    fn read_only_is_not_written() {
+        let dir = TempDir::new("sqlite_read_only");
+        let path = dir.join("jab.sqlite");
+
+        assert!(SqliteStorage::open_read_only(&path)
+            .unwrap()
+            .keys("imager")
+            .unwrap()
+            .is_empty());
+        assert!(!path.exists());
+        SqliteStorage::open(&path)
+            .unwrap()
+            .put("imager", "a", vec![1])
+            .unwrap();
+        let mut storage = SqliteStorage::open_read_only(&path).unwrap();
+        assert_eq!(storage.get("imager", "a").unwrap(), Some(vec![1]));
+        assert!(storage.put("imager", "a", vec![2]).is_err());
+    }
+}
@@ -6,27 +6,30 @@ use api::{
 use async_trait::async_trait;
 use bincode::{Decode, Encode};
 use bot::{
-    bot::command::BotCommandInfo,
+    bot::{command::BotCommandInfo, registry::CommandSpec},
     communicator::Communicate,
     module::{Module, PersistentModule},
     persistence::Persistence,
+    storage::ChatStorage,
 };
 use compact_str::CompactString;
 use eyre::{bail, eyre};
 use itertools::Itertools;
 use log::debug;
 use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     str::FromStr,
+    sync::{Mutex, MutexGuard},
 };
 
 #[derive(Default)]
 pub struct Archivarius {
-    chat_data: HashMap<ChatIntId, ChatData>,
+    chat_data: Mutex<HashMap<ChatIntId, ChatData>>,
 }
 
-#[derive(Debug, Default, Encode, Decode)]
+#[derive(Debug, Default, Encode, Decode, Serialize, Deserialize)]
 struct ChatData {
     pub active_command: Option<ActiveCommand>,
     pub messages: HashSet<ChatMessageInfo>,
@@ -39,11 +42,17 @@ impl Archivarius {
         Default::default()
     }
 
-    fn save_message(&mut self, message: &Message) -> eyre::Result<()> {
+    fn chat_data(&self) -> MutexGuard<'_, HashMap<ChatIntId, ChatData>> {
+        self.chat_data
+            .lock()
+            .expect("archivarius data lock poisoned")
+    }
+
+    fn save_message(&self, message: &Message) -> eyre::Result<()> {
         let Some(original_message) = &message.reply_to_message else {
             bail!("replied message does not exist, command message = {message:?}");
         };
-        self.chat_data
+        self.chat_data()
             .entry(message.chat.id)
             .or_default()
             .messages
@@ -52,16 +61,17 @@ impl Archivarius {
     }
 
     async fn forward(
-        &mut self,
+        &self,
         comm: &dyn Communicate,
         dest_chat_id: ChatIntId,
     ) -> eyre::Result<Option<Message>> {
-        let Some(address) = self.chat_data.get(&dest_chat_id).and_then(|data| {
+        let address = self.chat_data().get(&dest_chat_id).and_then(|data| {
             data.messages
                 .iter()
                 .choose(&mut rand::thread_rng())
-                .map(|i| i.address())
-        }) else {
+                .map(|i| i.address().clone())
+        });
+        let Some(address) = address else {
             return Ok(None);
         };
         // fixme: remove non-existing messages
@@ -116,27 +126,17 @@ impl Archivarius {
         Ok(())
     }
 
-    async fn guess(&mut self, comm: &dyn Communicate, message: &Message) -> eyre::Result<()> {
-        let Some(data) = self.chat_data.get_mut(&message.chat.id) else {
-            comm.reply_message(
-                "No messages to guess",
-                message.chat.id.into(),
-                message.message_id,
-                None,
-            )
-            .await?
-            .into_result()?;
-            return Ok(());
-        };
-
+    async fn guess(&self, comm: &dyn Communicate, message: &Message) -> eyre::Result<()> {
         // Self::tell_the_answer(&data, comm, message).await?;
 
-        let Some(info) = data
-            .messages
-            .iter()
-            .filter(|info| info.author_info.is_some())
-            .choose(&mut rand::thread_rng())
-        else {
+        let address = self.chat_data().get(&message.chat.id).and_then(|data| {
+            data.messages
+                .iter()
+                .filter(|info| info.author_info.is_some())
+                .choose(&mut rand::thread_rng())
+                .map(|info| info.address().clone())
+        });
+        let Some(address) = address else {
             comm.reply_message(
                 "No messages to guess",
                 message.chat.id.into(),
@@ -148,8 +148,6 @@ impl Archivarius {
             return Ok(());
         };
 
-        let address = info.address();
-
         comm.copy_message(
             message.chat.id.into(),
             None,
@@ -167,64 +165,86 @@ impl Archivarius {
         .await?
         .into_result()?;
 
-        data.guesses.message_id.replace(address.message_id);
+        if let Some(data) = self.chat_data().get_mut(&message.chat.id) {
+            data.guesses.message_id.replace(address.message_id);
+        }
 
         Ok(())
     }
 
-    async fn check_guess(
-        &mut self,
-        comm: &dyn Communicate,
-        message: &Message,
-    ) -> eyre::Result<bool> {
-        let Some((users, messages, guess_info, guess_message_id)) =
-            self.chat_data.get_mut(&message.chat.id).and_then(|d| {
-                let message_id = d.guesses.message_id?;
-                Some((&mut d.users, &d.messages, &mut d.guesses, message_id))
-            })
-        else {
-            bail!("cannot check the guess: the game has not yet started");
-        };
-
-        let message_info = messages
-            .get(&ChatMessageInfo::new(guess_message_id))
-            .ok_or(eyre!(
-                "guess message info not found, guess_message_id = {guess_message_id}"
+    async fn check_guess(&self, comm: &dyn Communicate, message: &Message) -> eyre::Result<bool> {
+        {
+            let mut chat_data = self.chat_data();
+            let Some((users, messages, guess_info, guess_message_id)) =
+                chat_data.get_mut(&message.chat.id).and_then(|d| {
+                    let message_id = d.guesses.message_id?;
+                    Some((&mut d.users, &d.messages, &mut d.guesses, message_id))
+                })
+            else {
+                bail!("cannot check the guess: the game has not yet started");
+            };
+
+            let message_info = messages
+                .get(&ChatMessageInfo::new(guess_message_id))
+                .ok_or(eyre!(
+                    "guess message info not found, guess_message_id = {guess_message_id}"
+                ))?;
+
+            let author = message_info.author_info.as_ref().ok_or(eyre!(
+                "cannot check the guess: the game has not yet started"
             ))?;
 
-        let author = message_info.author_info.as_ref().ok_or(eyre!(
-            "cannot check the guess: the game has not yet started"
-        ))?;
-
-        debug!("{author:?} - {:?}", message.text);
+            debug!("{author:?} - {:?}", message.text);
 
-        let text = message
-            .text
-            .as_ref()
-            .ok_or(eyre!("not a text message, message = {message:?}"))?;
+            let text = message
+                .text
+                .as_ref()
+                .ok_or(eyre!("not a text message, message = {message:?}"))?;
 
-        if author == text.as_str() {
+            if author != text.as_str() {
+                return Ok(false);
+            }
             let winner = message
                 .from
                 .as_ref()
                 .ok_or(eyre!("cannot add points to a non-user entity"))?;
             guess_info.finish_game(winner.id);
             users.insert(author.clone());
-            comm.reply_message(
-                "Exactly! +1 point",
-                message.chat.id.into(),
-                message.message_id,
-                None,
-            )
-            .await?
-            .into_result()?;
-            return Ok(true);
         }
-        Ok(false)
+        comm.reply_message(
+            "Exactly! +1 point",
+            message.chat.id.into(),
+            message.message_id,
+            None,
+        )
+        .await?
+        .into_result()?;
+        Ok(true)
     }
 
     async fn points(&self, message: &Message, comm: &dyn Communicate) -> eyre::Result<()> {
-        let Some(data) = self.chat_data.get(&message.chat.id) else {
+        let leaders: Option<CompactString> = self.chat_data().get(&message.chat.id).map(|data| {
+            Itertools::intersperse(
+                data.guesses
+                    .points
+                    .iter()
+                    .sorted_by(|(_, p1), (_, p2)| Ord::cmp(p2, p1))
+                    .filter_map(|(id, points)| {
+                        let user_info = data.users.get(&UserInfo {
+                            id: *id,
+                            ..Default::default()
+                        })?;
+                        Some((
+                            user_info.username.as_ref().unwrap_or(&user_info.full_name),
+                            points,
+                        ))
+                    })
+                    .map(|(name, score)| format!("{name} \t{score}")),
+                "\n".into(),
+            )
+            .collect()
+        });
+        let Some(leaders) = leaders else {
             comm.reply_message(
                 "The list is empty",
                 message.chat.id.into(),
@@ -236,25 +256,6 @@ impl Archivarius {
             return Ok(());
         };
 
-        let leaders: CompactString = Itertools::intersperse(
-            data.guesses
-                .points
-                .iter()
-                .sorted_by(|(_, p1), (_, p2)| Ord::cmp(p2, p1))
-                .filter_map(|(id, points)| {
-                    let user_info = data.users.get(&UserInfo {
-                        id: *id,
-                        ..Default::default()
-                    })?;
-                    Some((
-                        user_info.username.as_ref().unwrap_or(&user_info.full_name),
-                        points,
-                    ))
-                })
-                .map(|(name, score)| format!("{name} \t{score}")),
-            "\n".into(),
-        )
-        .collect();
         comm.reply_message(
             &format!("```\n{leaders}\n```"),
             message.chat.id.into(),
@@ -267,18 +268,18 @@ impl Archivarius {
         Ok(())
     }
 
-    fn handle_active_command(&mut self, message: &Message) {
-        let Some(active_command) = self
-            .chat_data
+    fn handle_active_command(&self, message: &Message) {
+        let mut chat_data = self.chat_data();
+        let Some(active_command) = chat_data
             .get(&message.chat.id)
             .and_then(|d| d.active_command.as_ref())
         else {
             return;
         };
-        match active_command {
+        match *active_command {
             ActiveCommand::DevSave(chat_id) => {
-                self.chat_data
-                    .entry(*chat_id)
+                chat_data
+                    .entry(chat_id)
                     .or_default()
                     .messages
                     .insert(message.into());
@@ -289,25 +290,35 @@ impl Archivarius {
 
 #[async_trait]
 impl Module for Archivarius {
+    fn commands(&self) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::new("forward", "forward a random saved message"),
+            CommandSpec::new("save", "save the replied message"),
+            CommandSpec::new("guess", "guess the author of a random saved message"),
+            CommandSpec::new("remove", "remove the replied message from the saved ones"),
+            CommandSpec::new("points", "guessing leaderboard"),
+            CommandSpec::new("dev_save", "save new messages of this chat to another one")
+                .usage("<chat id>")
+                .owner_only(),
+            CommandSpec::new("dev_stop", "stop saving new messages").owner_only(),
+        ]
+    }
+
+    async fn on_message(&self, comm: &dyn Communicate, message: &Message) -> eyre::Result<()> {
+        self.handle_active_command(message);
+        if let Err(err) = self.check_guess(comm, message).await {
+            debug!("{err}");
+        }
+        Ok(())
+    }
+
     async fn try_execute_command(
-        &mut self,
+        &self,
         comm: &dyn Communicate,
         cmd: &BotCommandInfo,
         message: &Message,
     ) -> eyre::Result<()> {
-        let command_name = match CommandName::from_str(cmd.name().as_str()) {
-            Ok(name) => name,
-            Err(err) => {
-                debug!("{err}");
-                self.handle_active_command(message);
-                if let Err(err) = self.check_guess(comm, message).await {
-                    debug!("{err}");
-                }
-                return Ok(());
-            }
-        };
-
-        match command_name {
+        match CommandName::from_str(cmd.name().as_str())? {
             CommandName::Forward => {
                 if self.forward(comm, message.chat.id).await?.is_none() {
                     comm.reply_message(
@@ -331,7 +342,7 @@ impl Module for Archivarius {
             }
             CommandName::Remove => {
                 if let Some(messages) = self
-                    .chat_data
+                    .chat_data()
                     .get_mut(&message.chat.id)
                     .map(|d| &mut d.messages)
                 {
@@ -345,20 +356,9 @@ impl Module for Archivarius {
                 self.points(message, comm).await?;
             }
             CommandName::DevSave => {
-                let Ok(chat_id) = cmd.query().parse::<ChatIntId>() else {
-                    comm.reply_message(
-                        &format!(
-                            "failed to parse '{}' as chat id to save new messages",
-                            cmd.query()
-                        ),
-                        message.chat.id.into(),
-                        message.message_id,
-                        None,
-                    )
-                    .await?
-                    .into_result()?;
-                    return Ok(());
-                };
+                let mut args = cmd.args(message)?;
+                let chat_id = args.required::<ChatIntId>("chat id")?;
+                args.finish()?;
                 comm.reply_message(
                     &format!("listening to messages for '{chat_id}' now..."),
                     message.chat.id.into(),
@@ -367,19 +367,18 @@ impl Module for Archivarius {
                 )
                 .await?
                 .into_result()?;
-                self.chat_data
+                self.chat_data()
                     .entry(message.chat.id)
                     .or_default()
                     .active_command
                     .replace(ActiveCommand::DevSave(chat_id));
             }
             CommandName::DevStop => {
-                if self
-                    .chat_data
-                    .get(&message.chat.id)
-                    .and_then(|d| d.active_command.as_ref())
-                    .is_none()
-                {
+                let active_command = self
+                    .chat_data()
+                    .get_mut(&message.chat.id)
+                    .and_then(|d| d.active_command.take());
+                if active_command.is_none() {
                     comm.reply_message(
                         "no active command",
                         message.chat.id.into(),
@@ -389,10 +388,6 @@ impl Module for Archivarius {
                     .await?
                     .into_result()?;
                 } else {
-                    self.chat_data
-                        .entry(message.chat.id)
-                        .or_default()
-                        .active_command = None;
                     comm.reply_message("done", message.chat.id.into(), message.message_id, None)
                         .await?
                         .into_result()?;
@@ -403,26 +398,78 @@ impl Module for Archivarius {
     }
 }
 
+/// Key the data of a chat is kept under
+const CHAT_KEY: &str = "chat";
+
 impl Persistence for Archivarius {
     type Input = Vec<u8>;
     type Output = Vec<u8>;
 
+    /// The chats are kept under their own keys, see `save_chats`
     fn serialize(&self) -> eyre::Result<Self::Output> {
         Ok(bincode::encode_to_vec(
-            &self.chat_data,
+            HashMap::<ChatIntId, ChatData>::new(),
             bincode::config::standard(),
         )?)
     }
 
+    /// Only the data saved before the chat keys has any chats
     fn deserialize(&mut self, bytes: Self::Input) -> eyre::Result<()>
     where
         Self: Sized,
     {
-        self.chat_data = bincode::decode_from_slice::<HashMap<ChatIntId, ChatData>, _>(
-            bytes.as_slice(),
-            bincode::config::standard(),
-        )?
-        .0;
+        self.chat_data = Mutex::new(
+            bincode::decode_from_slice::<HashMap<ChatIntId, ChatData>, _>(
+                bytes.as_slice(),
+                bincode::config::standard(),
+            )?
+            .0,
+        );
+        Ok(())
+    }
+
+    /// Unchanged chats are left as they are by the storage
+    fn save_chats(&self, storage: &mut ChatStorage) -> eyre::Result<()> {
+        let chat_data = self.chat_data();
+        for chat_id in storage.chats()? {
+            if !chat_data.contains_key(&chat_id) {
+                storage.delete(chat_id, CHAT_KEY)?;
+            }
+        }
+        for (chat_id, data) in chat_data.iter() {
+            let bytes = bincode::encode_to_vec(data, bincode::config::standard())?;
+            storage.put(*chat_id, CHAT_KEY, bytes)?;
+        }
+        Ok(())
+    }
+
+    fn load_chats(&mut self, storage: &ChatStorage) -> eyre::Result<()> {
+        let mut chats = HashMap::new();
+        for chat_id in storage.chats()? {
+            let Some(bytes) = storage.get(chat_id, CHAT_KEY)? else {
+                continue;
+            };
+            let data = bincode::decode_from_slice::<ChatData, _>(
+                bytes.as_slice(),
+                bincode::config::standard(),
+            )
+            .map_err(|err| eyre!("failed to load chat {chat_id}, {err}"))?
+            .0;
+            chats.insert(chat_id, data);
+        }
+        self.chat_data
+            .get_mut()
+            .expect("archivarius data lock poisoned")
+            .extend(chats);
+        Ok(())
+    }
+
+    fn export(&self) -> eyre::Result<serde_json::Value> {
+        Ok(serde_json::to_value(&*self.chat_data())?)
+    }
+
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        self.chat_data = Mutex::new(serde_json::from_value(value)?);
         Ok(())
     }
 }
@@ -458,7 +505,70 @@ impl FromStr for CommandName {
     }
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Copy, Clone, Encode, Decode, Serialize, Deserialize)]
 enum ActiveCommand {
     DevSave(ChatIntId),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bot::storage::{sqlite::SqliteStorage, Storage};
+
+    #[test]
+    fn chats_are_kept_under_their_keys() {
+        let mut storage = SqliteStorage::in_memory().unwrap();
+        let archivarius = Archivarius::new();
+        archivarius.chat_data().insert(
+            -100,
+            ChatData {
+                active_command: Some(ActiveCommand::DevSave(-200)),
+                ..Default::default()
+            },
+        );
+        archivarius.chat_data().insert(-200, Default::default());
+        archivarius
+            .save_chats(&mut ChatStorage::new(&mut storage, "archivarius"))
+            .unwrap();
+        assert_eq!(
+            storage.namespaces().unwrap(),
+            ["archivarius/-100", "archivarius/-200"]
+        );
+
+        archivarius.chat_data().remove(&-200);
+        archivarius
+            .save_chats(&mut ChatStorage::new(&mut storage, "archivarius"))
+            .unwrap();
+        assert_eq!(storage.namespaces().unwrap(), ["archivarius/-100"]);
+
+        let mut loaded = Archivarius::new();
+        loaded
+            .deserialize(archivarius.serialize().unwrap())
+            .unwrap();
+        loaded
+            .load_chats(&ChatStorage::new(&mut storage, "archivarius"))
+            .unwrap();
+        let chat_data = loaded.chat_data();
+        assert_eq!(chat_data.len(), 1);
+        assert!(matches!(
+            chat_data[&-100].active_command,
+            Some(ActiveCommand::DevSave(-200))
+        ));
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let archivarius = Archivarius::new();
+        let mut data = ChatData::default();
+        data.guesses.points.insert(42, 3);
+        data.messages.insert(ChatMessageInfo::new(10));
+        archivarius.chat_data().insert(-100, data);
+
+        let exported = archivarius.export().unwrap();
+        assert_eq!(exported["-100"]["guesses"]["points"]["42"], 3);
+        let mut imported = Archivarius::new();
+        imported.import(exported.clone()).unwrap();
+        assert_eq!(imported.export().unwrap(), exported);
+        assert_eq!(imported.chat_data()[&-100].guesses.points[&42], 3);
+    }
+}
@@ -1,8 +1,9 @@
 use api::basic_types::{MessageId, UserId};
 use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Encode, Decode, Debug, Default)]
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Default)]
 pub(crate) struct ChatGuessInfo {
     pub points: HashMap<UserId, usize>,
     pub message_id: Option<MessageId>,
@@ -4,15 +4,16 @@ use api::{
     proto::Message,
 };
 use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Encode, Decode, Default, Clone)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, Default, Clone)]
 pub(crate) struct MessageAddress {
     pub chat_id: ChatIntId,
     pub message_id: MessageId,
 }
 
-#[derive(Debug, Encode, Decode, Default)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, Default)]
 pub(crate) struct ChatMessageInfo {
     address: MessageAddress,
     original_address: Option<MessageAddress>,
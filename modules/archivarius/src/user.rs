@@ -1,8 +1,9 @@
 use api::{basic_types::UserId, proto::User};
 use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 
-#[derive(Encode, Decode, Debug, Default, Clone)]
+#[derive(Encode, Decode, Serialize, Deserialize, Debug, Default, Clone)]
 pub(crate) struct UserInfo {
     pub id: UserId,
     pub full_name: String,
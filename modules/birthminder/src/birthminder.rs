@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use chrono::{Local, NaiveDate};
+use chrono::Local;
 use compact_str::CompactString;
 use std::{
     collections::{hash_map::Entry, HashMap},
@@ -9,13 +9,16 @@ use std::{
 };
 
 use eyre::{bail, eyre};
-use log::debug;
 
 use api::{
     basic_types::UserId,
     proto::{Message, User},
 };
-use bot::{bot::command::BotCommandInfo, communicator::Communicate, module::Module};
+use bot::{
+    bot::{args::DayMonth, command::BotCommandInfo, registry::CommandSpec},
+    communicator::Communicate,
+    module::Module,
+};
 
 #[derive(Debug, Default)]
 pub struct Birthminder {
@@ -29,7 +32,7 @@ impl Birthminder {
 
     // todo: config with different kinds of wishes
 
-    pub fn save(&mut self, user: &User, date: NaiveDate) -> eyre::Result<()> {
+    pub fn save(&self, user: &User, date: DayMonth) -> eyre::Result<()> {
         let mut data = self.map.write().expect("birthday map lock poisoned");
         match data.0.entry(date) {
             Entry::Occupied(mut o) => {
@@ -42,7 +45,7 @@ impl Birthminder {
         Ok(())
     }
 
-    pub fn next_birthdays(&self) -> (NaiveDate, Vec<&UserData>) {
+    pub fn next_birthdays(&self) -> (DayMonth, Vec<&UserData>) {
         // let map = self.map.read().expect("birthday map lock poisoned");
         todo!()
     }
@@ -66,25 +69,25 @@ impl Birthminder {
 }
 
 #[derive(Debug, Default)]
-struct BirthdayMap(HashMap<NaiveDate, Vec<UserData>>);
+struct BirthdayMap(HashMap<DayMonth, Vec<UserData>>);
 
 impl BirthdayMap {
     pub fn _today_birthdays(&self) -> Option<&Vec<UserData>> {
-        let today = Local::now().date_naive();
+        let today = DayMonth::from(Local::now().date_naive());
         let Some(list) = self.0.get(&today) else {
             return None;
         };
         Some(list)
     }
 
-    pub fn _birthday_list(&self) -> Vec<(NaiveDate, UserData)> {
+    pub fn _birthday_list(&self) -> Vec<(DayMonth, UserData)> {
         self.0
             .iter()
             .flat_map(|(date, users)| users.iter().map(|user| (*date, user.clone())))
             .collect()
     }
 
-    pub fn _next_birthdays(&self) -> (NaiveDate, Vec<&UserData>) {
+    pub fn _next_birthdays(&self) -> (DayMonth, Vec<&UserData>) {
         // let mut today = Utc::now().date_naive();
         // self.0.iter().min_by_key(|(date, _)| date.);
         todo!()
@@ -130,25 +133,27 @@ impl FromStr for CommandName {
 
 #[async_trait]
 impl Module for Birthminder {
+    fn commands(&self) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::new("set", "remember your birthday").usage("<dd.mm>"),
+            CommandSpec::new("next", "upcoming birthdays"),
+        ]
+    }
+
     async fn try_execute_command(
-        &mut self,
+        &self,
         _comm: &dyn Communicate,
         cmd: &BotCommandInfo,
         message: &Message,
     ) -> eyre::Result<()> {
-        let name = match CommandName::from_str(cmd.name()) {
-            Ok(name) => name,
-            Err(err) => {
-                debug!("{err}");
-                return Ok(());
-            }
-        };
-        match name {
+        match CommandName::from_str(cmd.name())? {
             CommandName::Set => {
                 let user = message.from.as_ref().ok_or(eyre!(
                     "no user info to save birthday, message = {message:?}"
                 ))?;
-                let date = NaiveDate::parse_from_str(cmd.query().as_str(), "%d.%m")?;
+                let mut args = cmd.args(message)?;
+                let date = args.required::<DayMonth>("birthday")?;
+                args.finish()?;
                 self.save(user, date)?;
             }
             CommandName::Next => {}
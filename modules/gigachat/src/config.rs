@@ -0,0 +1,34 @@
+use api::params::eyre::{self, ensure};
+use compact_str::CompactString;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct GigaChatConfig {
+    pub https_url: CompactString,
+    pub token_request_url: CompactString,
+    /// Root certificate the API is signed with, relative to the work dir
+    pub cert_path: PathBuf,
+}
+
+impl GigaChatConfig {
+    pub fn verify(&self) -> eyre::Result<()> {
+        ensure!(!self.https_url.is_empty(), "gigachat url cannot be empty");
+        ensure!(
+            !self.token_request_url.is_empty(),
+            "gigachat token request url cannot be empty"
+        );
+        Ok(())
+    }
+}
+
+impl Default for GigaChatConfig {
+    fn default() -> Self {
+        Self {
+            https_url: "https://gigachat.devices.sberbank.ru/api/v1".into(),
+            token_request_url: "https://ngw.devices.sberbank.ru:9443/api/v2/oauth".into(),
+            cert_path: "modules/gigachat/russian_trusted_root_ca.cer".into(),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
+    config::GigaChatConfig,
     endpoints::ChatCompletions,
-    proto::GigaChatMessage,
+    proto::{GigaChatMessage, GigaChatRole},
     request::ChatCompletionsRequest,
     response::{AccessTokenResponse, ChatCompletionsResponse},
 };
@@ -16,7 +17,12 @@ use api::{
 };
 use async_trait::async_trait;
 use bot::{
-    bot::command::BotCommandInfo,
+    bot::{
+        chat_settings::SettingSpec,
+        command::BotCommandInfo,
+        registry::CommandSpec,
+        throttle::{Limits, Rate},
+    },
     communicator::Communicate,
     module::{Module, PersistentModule},
     persistence::Persistence,
@@ -29,9 +35,12 @@ use serde::Serialize;
 use std::{
     collections::{hash_map::Entry, HashMap},
     str::FromStr,
+    sync::Mutex,
+    time::Duration,
 };
 use uuid::Uuid;
 
+pub mod config;
 mod endpoints;
 mod proto;
 mod request;
@@ -40,34 +49,60 @@ mod response;
 pub struct GigaChat {
     https_url: CompactString,
     token_request_url: CompactString,
-    token_expires_at: Timestamp,
-    access_token: CompactString,
+    token: Mutex<AccessToken>,
     uuid: Uuid,
     cert: Certificate,
-    messages: HashMap<ChatIntId, Vec<GigaChatMessage>>,
+    messages: Mutex<HashMap<ChatIntId, Vec<GigaChatMessage>>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct AccessToken {
+    expires_at: Timestamp,
+    value: CompactString,
 }
 
 impl GigaChat {
+    /// Takes the certificate from the work dir named by `WORK_DIR`
     pub fn new() -> Self {
         let work_dir = std::env::var("WORK_DIR").expect("work dir not found");
-        let path =
-            std::path::Path::new(&work_dir).join("modules/gigachat/russian_trusted_root_ca.cer");
+        let mut config = GigaChatConfig::default();
+        config.cert_path = std::path::Path::new(&work_dir).join(config.cert_path);
+        Self::with_config(config).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// `cert_path` is taken as is, relative to the current dir
+    pub fn with_config(config: GigaChatConfig) -> eyre::Result<Self> {
+        config.verify()?;
+        let path = config.cert_path;
         let buf = std::fs::read(&path)
-            .unwrap_or_else(|err| panic!("cert not found on path '{path:?}', {err}"));
-        let cert = Certificate::from_pem(&buf).expect("wrong certificate format");
-        Self {
-            https_url: "https://gigachat.devices.sberbank.ru/api/v1".into(),
-            token_request_url: "https://ngw.devices.sberbank.ru:9443/api/v2/oauth".into(),
-            token_expires_at: Timestamp::now(),
-            access_token: Default::default(),
+            .map_err(|err| eyre!("cert not found on path '{path:?}', {err}"))?;
+        let cert = Certificate::from_pem(&buf)
+            .map_err(|err| eyre!("wrong certificate format, path = {path:?}, {err}"))?;
+        Ok(Self {
+            https_url: config.https_url,
+            token_request_url: config.token_request_url,
+            token: Mutex::new(AccessToken {
+                expires_at: Timestamp::now(),
+                value: Default::default(),
+            }),
             uuid: Uuid::new_v4(),
             cert,
             messages: Default::default(),
-        }
+        })
     }
-    pub async fn update_token_if_expired(&mut self) -> eyre::Result<()> {
-        if self.token_expires_at > Timestamp::now() + Timestamp::from(5) {
-            return Ok(());
+
+    fn token(&self) -> AccessToken {
+        self.token
+            .lock()
+            .expect("gigachat token lock poisoned")
+            .clone()
+    }
+
+    /// Returns a valid access token, requesting a new one if the current expires soon
+    pub async fn update_token_if_expired(&self) -> eyre::Result<CompactString> {
+        let token = self.token();
+        if token.expires_at > Timestamp::now() + Timestamp::from(5) {
+            return Ok(token.value);
         }
 
         let client_id =
@@ -94,25 +129,43 @@ impl GigaChat {
             .await?
             .json()
             .await?;
-        self.token_expires_at = response.expires_at;
-        self.access_token = response.access_token;
-        Ok(())
+        *self.token.lock().expect("gigachat token lock poisoned") = AccessToken {
+            expires_at: response.expires_at,
+            value: response.access_token.clone(),
+        };
+        Ok(response.access_token)
     }
 
+    /// `persona` is the system prompt the chat set, if any
     pub async fn chat_completions(
-        &mut self,
+        &self,
         query: &str,
         chat_id: ChatIntId,
+        persona: Option<&str>,
     ) -> eyre::Result<ChatCompletionsResponse> {
-        self.update_token_if_expired().await?;
+        let access_token = self.update_token_if_expired().await?;
 
         let url = format!("{}/{}", self.https_url, ChatCompletions::PATH);
 
-        let history = match self.messages.get(&chat_id) {
+        let mut history = match self
+            .messages
+            .lock()
+            .expect("gigachat messages lock poisoned")
+            .get(&chat_id)
+        {
             None => vec![],
             Some(vs) => vs.iter().rev().cloned().take(100).collect::<Vec<_>>(),
         };
 
+        if let Some(persona) = persona {
+            history.insert(
+                0,
+                GigaChatMessage {
+                    role: GigaChatRole::System,
+                    content: persona.into(),
+                },
+            );
+        }
         let data = ChatCompletionsRequest::latest(history, query);
 
         let client = Client::builder()
@@ -120,7 +173,7 @@ impl GigaChat {
             .build()?;
         let text = client
             .request(ChatCompletions::METHOD, url)
-            .bearer_auth(&self.access_token)
+            .bearer_auth(&access_token)
             .json(&data)
             .send()
             .await?
@@ -142,10 +195,8 @@ impl FromStr for CommandName {
     type Err = eyre::Report;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
+        match s {
             "gpt" => Ok(CommandName::Ask),
-            "гпт" => Ok(CommandName::Ask),
-            "жпт" => Ok(CommandName::Ask),
             "car_crash" => Ok(CommandName::CarCrash),
             _ => {
                 bail!("failed to recognize '{s}' as a possible command")
@@ -156,15 +207,39 @@ impl FromStr for CommandName {
 
 #[async_trait]
 impl Module for GigaChat {
+    fn commands(&self) -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::new("gpt", "ask GigaChat, the chat history is kept")
+                .aliases(&["гпт", "жпт"])
+                .usage("<question>")
+                .limits(Limits {
+                    per_user: Some(Rate::new(3, Duration::from_secs(60))),
+                    daily_per_user: Some(50),
+                    ..Default::default()
+                }),
+            CommandSpec::new("car_crash", "make GigaChat forget the chat history"),
+        ]
+    }
+
+    fn settings(&self) -> Vec<SettingSpec> {
+        vec![SettingSpec::new::<String>(
+            "persona",
+            "who GigaChat pretends to be in this chat",
+        )]
+    }
+
     async fn try_execute_command(
-        &mut self,
+        &self,
         comm: &dyn Communicate,
         cmd: &BotCommandInfo,
         message: &Message,
     ) -> eyre::Result<()> {
-        match CommandName::from_str(cmd.name().as_str()) {
-            Ok(CommandName::Ask) => {
-                let response = self.chat_completions(cmd.query(), message.chat.id).await?;
+        match CommandName::from_str(cmd.name().as_str())? {
+            CommandName::Ask => {
+                let persona = cmd.settings().get::<String>("persona")?;
+                let response = self
+                    .chat_completions(cmd.query(), message.chat.id, persona.as_deref())
+                    .await?;
 
                 ensure!(!response.choices.is_empty(), "no answer for {cmd:?}");
                 comm.send_chat_action(message.chat.id.into(), None, ChatAction::Typing)
@@ -214,15 +289,25 @@ impl Module for GigaChat {
                     .into_iter()
                     .map(|choice| choice.message)
                     .collect::<Vec<_>>();
-                match self.messages.entry(message.chat.id) {
+                match self
+                    .messages
+                    .lock()
+                    .expect("gigachat messages lock poisoned")
+                    .entry(message.chat.id)
+                {
                     Entry::Occupied(mut occupied) => occupied.get_mut().append(&mut messages),
                     Entry::Vacant(vacant) => {
                         vacant.insert(messages);
                     }
                 };
             }
-            Ok(CommandName::CarCrash) => {
-                if let Some(vs) = self.messages.get_mut(&message.chat.id) {
+            CommandName::CarCrash => {
+                if let Some(vs) = self
+                    .messages
+                    .lock()
+                    .expect("gigachat messages lock poisoned")
+                    .get_mut(&message.chat.id)
+                {
                     vs.clear();
                 }
                 comm.reply_message(
@@ -234,9 +319,6 @@ impl Module for GigaChat {
                 .await?
                 .into_result()?;
             }
-            Err(err) => {
-                debug!("{err}");
-            }
         }
         Ok(())
     }
@@ -247,12 +329,13 @@ impl Persistence for GigaChat {
     type Output = Vec<u8>;
 
     fn serialize(&self) -> eyre::Result<Self::Output> {
+        let token = self.token();
+        let messages = self
+            .messages
+            .lock()
+            .expect("gigachat messages lock poisoned");
         Ok(bincode::encode_to_vec(
-            (
-                self.token_expires_at.millis(),
-                self.access_token.as_str(),
-                &self.messages,
-            ),
+            (token.expires_at.millis(), token.value.as_str(), &*messages),
             bincode::config::standard(),
         )?)
     }
@@ -264,9 +347,25 @@ impl Persistence for GigaChat {
                 _,
             >(input.as_slice(), bincode::config::standard())?
             .0;
-        self.access_token = token.into();
-        self.token_expires_at = Timestamp::from_millis(expires_at);
-        self.messages = messages;
+        self.token = Mutex::new(AccessToken {
+            expires_at: Timestamp::from_millis(expires_at),
+            value: token.into(),
+        });
+        self.messages = Mutex::new(messages);
+        Ok(())
+    }
+
+    /// Only the chat histories, the access token is a secret and is requested anew anyway
+    fn export(&self) -> eyre::Result<serde_json::Value> {
+        let messages = self
+            .messages
+            .lock()
+            .expect("gigachat messages lock poisoned");
+        Ok(serde_json::to_value(&*messages)?)
+    }
+
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        self.messages = Mutex::new(serde_json::from_value(value)?);
         Ok(())
     }
 }
@@ -282,9 +381,10 @@ mod test {
     #[tokio::test]
     async fn get_new_token() {
         dotenv().ok();
-        let mut gigachat = GigaChat::new();
+        let gigachat = GigaChat::new();
         gigachat.update_token_if_expired().await.unwrap();
-        assert!(gigachat.token_expires_at <= Timestamp::now() + Timestamp::from(1860));
-        assert!(gigachat.token_expires_at > Timestamp::now() + Timestamp::from(1740))
+        let expires_at = gigachat.token().expires_at;
+        assert!(expires_at <= Timestamp::now() + Timestamp::from(1860));
+        assert!(expires_at > Timestamp::now() + Timestamp::from(1740))
     }
 }
@@ -1,8 +1,12 @@
 use eyre::ensure;
+use serde::Deserialize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct ImagerConfig {
+    /// Results fetched per query in chats that have not set their own
     pub limit: usize,
+    /// Attempts to send a result before giving up
     pub max_reply_attempts: usize,
 }
 
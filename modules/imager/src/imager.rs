@@ -1,13 +1,14 @@
 use async_trait::async_trait;
 use bincode::{Decode, Encode};
 use derive_more::Display;
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, sync::Mutex, time::Duration};
 
 use api::basic_types::ChatIntId;
 use eyre::{bail, ensure};
 use image_search::{Arguments, Format};
 use log::{debug, error};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{config::ImagerConfig, error::REPLIED_MESSAGE_NOT_FOUND};
 use api::{
@@ -15,7 +16,12 @@ use api::{
     response::CommonResponse,
 };
 use bot::{
-    bot::command::BotCommandInfo,
+    bot::{
+        chat_settings::SettingSpec,
+        command::BotCommandInfo,
+        registry::CommandSpec,
+        throttle::{Limits, Rate},
+    },
     communicator::Communicate,
     module::{Module, PersistentModule},
     persistence::Persistence,
@@ -23,13 +29,13 @@ use bot::{
 
 #[derive(Debug, Default)]
 pub struct Imager {
-    chat_data: ChatData,
+    chat_data: Mutex<ChatData>,
     config: ImagerConfig,
 }
 
 type ChatData = HashMap<ChatIntId, SearchData>;
 
-#[derive(Debug, Encode, Decode, Default)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, Default)]
 pub struct SearchData {
     last_format: ImageFormat,
     last_query: String,
@@ -53,7 +59,7 @@ impl From<CommandName> for Mode {
     }
 }
 
-#[derive(Debug, Default, PartialEq, Encode, Decode, Eq, Copy, Clone)]
+#[derive(Debug, Default, PartialEq, Encode, Decode, Serialize, Deserialize, Eq, Copy, Clone)]
 enum ImageFormat {
     #[default]
     Pic,
@@ -104,52 +110,51 @@ impl Imager {
         }
     }
 
-    async fn search(
-        data: &mut SearchData,
+    /// Returns the query searched and the chosen result
+    async fn search_data(
+        &self,
+        chat_id: ChatIntId,
         query: &str,
         mode: Mode,
         format: ImageFormat,
         limit: usize,
-    ) -> eyre::Result<String> {
-        if query.is_empty() {
-            ensure!(!data.last_query.is_empty(), "query is empty");
-        }
-        if (query.is_empty() || data.last_query == query)
-            && data.last_format == format
-            && !data.last_results.is_empty()
-        {
-            return Ok(Self::choose_result(data, mode));
-        }
-        data.last_format = format;
-        if !query.is_empty() {
-            data.last_query = query.into();
-        }
-        data.seq_index = 0;
+    ) -> eyre::Result<(String, String)> {
+        let query = {
+            let mut chat_data = self.chat_data.lock().expect("imager data lock poisoned");
+            match chat_data.get_mut(&chat_id) {
+                Some(data) => {
+                    if (query.is_empty() || data.last_query == query)
+                        && data.last_format == format
+                        && !data.last_results.is_empty()
+                    {
+                        return Ok((data.last_query.clone(), Self::choose_result(data, mode)));
+                    }
+                    if query.is_empty() {
+                        data.last_query.clone()
+                    } else {
+                        query.to_string()
+                    }
+                }
+                None => query.to_string(),
+            }
+        };
+        ensure!(!query.is_empty(), "query is empty");
+
         let args = match format {
-            ImageFormat::Pic => Arguments::new(&data.last_query, limit),
-            ImageFormat::Gif => Arguments::new(&data.last_query, limit).format(Format::Gif),
+            ImageFormat::Pic => Arguments::new(&query, limit),
+            ImageFormat::Gif => Arguments::new(&query, limit).format(Format::Gif),
         };
-        data.last_results = image_search::urls(args.clone()).await?;
-        ensure!(!data.last_results.is_empty(), "no results");
-        let url = Self::choose_result(data, mode);
-        Ok(url)
-    }
+        let results = image_search::urls(args).await?;
+        ensure!(!results.is_empty(), "no results");
 
-    async fn search_data(
-        &mut self,
-        chat_id: ChatIntId,
-        query: &str,
-        mode: Mode,
-        format: ImageFormat,
-    ) -> eyre::Result<String> {
-        if let Some(data) = self.chat_data.get_mut(&chat_id) {
-            Self::search(data, query, mode, format, self.config.limit).await
-        } else {
-            let mut data = SearchData::default();
-            let url = Self::search(&mut data, query, mode, format, self.config.limit).await?;
-            let _ = self.chat_data.insert(chat_id, data);
-            Ok(url)
-        }
+        let mut chat_data = self.chat_data.lock().expect("imager data lock poisoned");
+        let data = chat_data.entry(chat_id).or_default();
+        data.last_format = format;
+        data.last_query = query.clone();
+        data.last_results = results;
+        data.seq_index = 0;
+        let url = Self::choose_result(data, mode);
+        Ok((query, url))
     }
 }
 
@@ -167,27 +172,9 @@ impl FromStr for CommandName {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "please" => Ok(CommandName::Please),
-            "Please" => Ok(CommandName::Please),
-            "плис" => Ok(CommandName::Please),
-            "Плис" => Ok(CommandName::Please),
-            "плиз" => Ok(CommandName::Please),
-            "Плиз" => Ok(CommandName::Please),
-            "Пж" => Ok(CommandName::Please),
-            "пж" => Ok(CommandName::Please),
-            "Pls" => Ok(CommandName::Pls),
             "pls" => Ok(CommandName::Pls),
-            "плс" => Ok(CommandName::Pls),
-            "Плс" => Ok(CommandName::Pls),
-            "плз" => Ok(CommandName::Pls),
-            "Плз" => Ok(CommandName::Pls),
-            "Gif" => Ok(CommandName::Gif),
             "gif" => Ok(CommandName::Gif),
-            "Гиф" => Ok(CommandName::Gif),
-            "гиф" => Ok(CommandName::Gif),
-            "Gif1" => Ok(CommandName::Gif1),
             "gif1" => Ok(CommandName::Gif1),
-            "Гиф1" => Ok(CommandName::Gif1),
-            "гиф1" => Ok(CommandName::Gif1),
             _ => {
                 bail!("failed to recognize '{s}' as a possible command")
             }
@@ -197,34 +184,66 @@ impl FromStr for CommandName {
 
 #[async_trait]
 impl Module for Imager {
+    fn commands(&self) -> Vec<CommandSpec> {
+        // every call may be a search, which the search quota is spent on
+        let limits = Limits {
+            per_user: Some(Rate::new(5, Duration::from_secs(10))),
+            daily_per_chat: Some(1000),
+            group: Some("imager"),
+            ..Default::default()
+        };
+        vec![
+            CommandSpec::new("pls", "random picture, for the last query if none given")
+                .aliases(&["плс", "плз"])
+                .usage("[query]")
+                .limits(limits.clone()),
+            CommandSpec::new("please", "next picture in the search order")
+                .aliases(&["плис", "плиз", "пж"])
+                .usage("[query]")
+                .limits(limits.clone()),
+            CommandSpec::new("gif", "random gif, for the last query if none given")
+                .aliases(&["гиф"])
+                .usage("[query]")
+                .limits(limits.clone()),
+            CommandSpec::new("gif1", "next gif in the search order")
+                .aliases(&["гиф1"])
+                .usage("[query]")
+                .limits(limits),
+        ]
+    }
+
+    fn settings(&self) -> Vec<SettingSpec> {
+        vec![SettingSpec::new::<usize>(
+            "limit",
+            "number of search results to choose from, 1-100",
+        )]
+    }
+
     async fn try_execute_command(
-        &mut self,
+        &self,
         comm: &dyn Communicate,
         cmd: &BotCommandInfo,
         message: &Message,
     ) -> eyre::Result<()> {
-        let name = match CommandName::from_str(cmd.name().as_str()) {
-            Ok(name) => name,
-            Err(err) => {
-                debug!("{err}");
-                return Ok(());
-            }
-        };
+        let name = CommandName::from_str(cmd.name().as_str())?;
         let format: ImageFormat = name.into();
-        let (action_sent, url) = tokio::join!(
+        let limit = cmd
+            .settings()
+            .get::<usize>("limit")?
+            .unwrap_or(self.config.limit)
+            .clamp(1, 100);
+        let (action_sent, result) = tokio::join!(
             comm.send_chat_action(message.chat.id.into(), None, ChatAction::UploadPhoto),
-            self.search_data(message.chat.id, cmd.query().as_str(), name.into(), format)
+            self.search_data(
+                message.chat.id,
+                cmd.query().as_str(),
+                name.into(),
+                format,
+                limit
+            )
         );
-        let url = url?;
+        let (query, url) = result?;
         let mut n = self.config.max_reply_attempts;
-        let query = self
-            .chat_data
-            .get(&message.chat.id)
-            .unwrap_or_else(|| {
-                panic!("data not found for a search completed just now, message = {message:?}")
-            })
-            .last_query
-            .as_str();
         debug!("result for '{query}': '{url}'");
         let mut reply_id = Some(message.message_id);
         while n > 0 {
@@ -272,8 +291,9 @@ impl Persistence for Imager {
     type Output = Vec<u8>;
 
     fn serialize(&self) -> eyre::Result<Self::Output> {
+        let chat_data = self.chat_data.lock().expect("imager data lock poisoned");
         Ok(bincode::encode_to_vec(
-            &self.chat_data,
+            &*chat_data,
             bincode::config::standard(),
         )?)
     }
@@ -282,11 +302,23 @@ impl Persistence for Imager {
     where
         Self: Sized,
     {
-        self.chat_data = bincode::decode_from_slice::<ChatData, _>(
-            input.as_slice(),
-            bincode::config::standard(),
-        )?
-        .0;
+        self.chat_data = Mutex::new(
+            bincode::decode_from_slice::<ChatData, _>(
+                input.as_slice(),
+                bincode::config::standard(),
+            )?
+            .0,
+        );
+        Ok(())
+    }
+
+    fn export(&self) -> eyre::Result<serde_json::Value> {
+        let chat_data = self.chat_data.lock().expect("imager data lock poisoned");
+        Ok(serde_json::to_value(&*chat_data)?)
+    }
+
+    fn import(&mut self, value: serde_json::Value) -> eyre::Result<()> {
+        self.chat_data = Mutex::new(serde_json::from_value(value)?);
         Ok(())
     }
 }
@@ -295,26 +327,20 @@ impl PersistentModule for Imager {}
 
 #[cfg(test)]
 mod test {
-    use crate::imager::CommandName;
-    use std::str::FromStr;
+    use crate::imager::Imager;
+    use bot::{bot::registry::CommandRegistry, module::Module};
 
     #[test]
-    fn please_from_str() {
-        let cmd = CommandName::from_str("please").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("Please").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("плис").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("Плис").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("плиз").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("Плиз").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("Пж").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
-        let cmd = CommandName::from_str("пж").unwrap();
-        assert!(matches!(cmd, CommandName::Please));
+    fn please_aliases() {
+        let mut registry = CommandRegistry::default();
+        assert!(registry
+            .register("imager", Imager::new().commands())
+            .is_empty());
+        for alias in [
+            "please", "Please", "плис", "Плис", "плиз", "Плиз", "Пж", "пж",
+        ] {
+            let (_, command) = registry.resolve(alias).unwrap();
+            assert_eq!(command.name, "please");
+        }
     }
 }
@@ -1,150 +1,434 @@
-use bot::connector::ConnectorMode;
+use api::basic_types::ChatIntId;
+use api::basic_types::UserId;
+use bot::{
+    bot::{middleware::FilterConfig, supervisor::SupervisorConfig},
+    connector::{config::ConnectorConfig, health::BackoffConfig, ConnectorMode},
+    storage::StorageBackend,
+};
 use compact_str::CompactString;
-use eyre::ensure;
+use eyre::{ensure, eyre};
+use gigachat::config::GigaChatConfig;
+use imager::config::ImagerConfig;
 use serde::Deserialize;
-use std::path::Path;
+use std::{collections::HashSet, fmt::Display, path::Path, str::FromStr, time::Duration};
 
+/// Every setting can be overridden with an env var, see [`GlobalConfig::apply_env`]
 #[derive(Debug, Deserialize)]
+#[serde(default)]
 pub struct GlobalConfig {
-    #[serde(default)]
-    pub connector_mode: ConnectorMode,
-    pub data_file_name: CompactString,
-    #[serde(default)]
-    pub skip_missed_updates: bool,
+    /// `jab3.data` for the file storage and `jab3.sqlite` for SQLite if not set,
+    /// so that switching the storage never opens the other one's file
+    pub data_file_name: Option<CompactString>,
+    /// `file` or `sqlite`
+    pub storage: StorageBackend,
+    /// How often the data is saved besides on shutdown, s, 0 to save on shutdown only
+    pub autosave_interval: u64,
+    /// Number of previous data files kept by the file storage, one is taken per start and day
+    pub backups: usize,
+    /// Whether `pls cats` works as `/pls cats` in chats that have not chosen otherwise
+    pub bare_word_commands: bool,
+    /// Comma separated ids of the users allowed to call any command in any chat
+    pub owner_ids: CompactString,
+    pub connector: ConnectorConfig,
+    pub backoff: BackoffSection,
+    pub supervisor: SupervisorSection,
+    pub filters: FiltersSection,
+    pub imager: ImagerConfig,
+    pub gigachat: GigaChatConfig,
+    /// Same as in `<connector>`, kept for the configs written before it
+    connector_mode: Option<ConnectorMode>,
+    /// Same as in `<connector>`, kept for the configs written before it
+    skip_missed_updates: Option<bool>,
 }
 
 impl Default for GlobalConfig {
     fn default() -> Self {
         Self {
-            connector_mode: Default::default(),
-            data_file_name: "jab3.data".into(),
-            skip_missed_updates: false,
+            data_file_name: None,
+            storage: Default::default(),
+            autosave_interval: 5 * 60,
+            backups: 3,
+            bare_word_commands: true,
+            owner_ids: Default::default(),
+            connector: Default::default(),
+            backoff: Default::default(),
+            supervisor: Default::default(),
+            filters: Default::default(),
+            imager: Default::default(),
+            gigachat: Default::default(),
+            connector_mode: None,
+            skip_missed_updates: None,
         }
     }
 }
 
 impl GlobalConfig {
-    fn validate(&self) -> eyre::Result<()> {
+    pub fn validate(&self) -> eyre::Result<()> {
         ensure!(
-            !self.data_file_name.is_empty(),
+            !self.data_file_name().is_empty(),
             "data file name cannot be empty"
         );
+        self.owners()?;
+        self.connector.verify()?;
+        self.backoff.verify()?;
+        ensure!(
+            self.supervisor.timeout > 0,
+            "module timeout must be positive"
+        );
+        self.filters()?;
+        self.imager.verify()?;
+        self.gigachat.verify()?;
         Ok(())
     }
 
+    /// Reads the file, applies the env overrides and validates the result
     pub fn from_file(path: impl AsRef<Path>) -> eyre::Result<Self> {
-        let contents = std::fs::read_to_string(path)?;
-        let config = serde_xml_rs::from_str::<Self>(contents.as_str())?;
+        let mut config = Self::parse(path)?;
+        config.apply_env()?;
         config.validate()?;
         Ok(config)
     }
+
+    /// Reads the file as it is, without the env overrides
+    pub fn parse(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut config = serde_xml_rs::from_str::<Self>(contents.as_str())?;
+        if let Some(mode) = config.connector_mode.take() {
+            config.connector.mode = mode;
+        }
+        if let Some(skip) = config.skip_missed_updates.take() {
+            config.connector.skip_missed_updates = skip;
+        }
+        Ok(config)
+    }
+
+    /// Env vars take precedence over the file, `WEBHOOK_*` keep their old names,
+    /// `OWNER_IDS` is read as an alias of `JAB_OWNER_IDS`
+    pub fn apply_env(&mut self) -> eyre::Result<()> {
+        env_optional("JAB_DATA_FILE_NAME", &mut self.data_file_name)?;
+        env_override("JAB_STORAGE", &mut self.storage)?;
+        env_override("JAB_AUTOSAVE_INTERVAL", &mut self.autosave_interval)?;
+        env_override("JAB_BACKUPS", &mut self.backups)?;
+        env_flag("JAB_BARE_WORD_COMMANDS", &mut self.bare_word_commands)?;
+        env_override("OWNER_IDS", &mut self.owner_ids)?;
+        env_override("JAB_OWNER_IDS", &mut self.owner_ids)?;
+
+        let connector = &mut self.connector;
+        env_override("JAB_CONNECTOR_MODE", &mut connector.mode)?;
+        env_optional("JAB_UPDATE_LIMIT", &mut connector.update_limit)?;
+        env_optional("JAB_POLLING_TIMEOUT", &mut connector.timeout)?;
+        env_flag(
+            "JAB_SKIP_MISSED_UPDATES",
+            &mut connector.skip_missed_updates,
+        )?;
+        let webhook = &mut connector.webhook;
+        env_override("WEBHOOK_HTTPS_URL", &mut webhook.https_url)?;
+        env_optional("WEBHOOK_IP_V4_ADDR", &mut webhook.ip_address)?;
+        env_optional("WEBHOOK_LISTEN_ADDR", &mut webhook.listen_address)?;
+        env_override("WEBHOOK_PORT", &mut webhook.port)?;
+        env_flag("WEBHOOK_TLS", &mut webhook.tls)?;
+        env_flag("WEBHOOK_UPLOAD_CERT", &mut webhook.upload_certificate)?;
+
+        let backoff = &mut self.backoff;
+        env_override("JAB_BACKOFF_INITIAL_DELAY", &mut backoff.initial_delay)?;
+        env_override("JAB_BACKOFF_MAX_DELAY", &mut backoff.max_delay)?;
+        env_override("JAB_BACKOFF_MULTIPLIER", &mut backoff.multiplier)?;
+        env_override("JAB_BACKOFF_JITTER", &mut backoff.jitter)?;
+        env_override(
+            "JAB_BACKOFF_CIRCUIT_THRESHOLD",
+            &mut backoff.circuit_threshold,
+        )?;
+        env_override("JAB_BACKOFF_CONFLICT_DELAY", &mut backoff.conflict_delay)?;
+
+        let supervisor = &mut self.supervisor;
+        env_override("JAB_SUPERVISOR_TIMEOUT", &mut supervisor.timeout)?;
+        env_optional(
+            "JAB_SUPERVISOR_FAILURE_LIMIT",
+            &mut supervisor.failure_limit,
+        )?;
+        env_flag(
+            "JAB_SUPERVISOR_REPLY_ON_ERROR",
+            &mut supervisor.reply_on_error,
+        )?;
+
+        let filters = &mut self.filters;
+        env_override("JAB_FILTERS_ALLOWED_CHATS", &mut filters.allowed_chats)?;
+        env_override("JAB_FILTERS_BANNED_USERS", &mut filters.banned_users)?;
+        env_flag("JAB_FILTERS_IGNORE_BOTS", &mut filters.ignore_bots)?;
+        env_flag(
+            "JAB_FILTERS_IGNORE_FORWARDED",
+            &mut filters.ignore_forwarded,
+        )?;
+        env_optional("JAB_FILTERS_METRICS_EVERY", &mut filters.metrics_every)?;
+
+        env_override("JAB_IMAGER_LIMIT", &mut self.imager.limit)?;
+        env_override("JAB_GIGACHAT_URL", &mut self.gigachat.https_url)?;
+        env_override(
+            "JAB_GIGACHAT_TOKEN_URL",
+            &mut self.gigachat.token_request_url,
+        )?;
+        env_override("JAB_GIGACHAT_CERT_PATH", &mut self.gigachat.cert_path)?;
+        Ok(())
+    }
+
+    pub fn owners(&self) -> eyre::Result<HashSet<UserId>> {
+        parse_ids(&self.owner_ids, "owner")
+    }
+
+    pub fn data_file_name(&self) -> CompactString {
+        match (&self.data_file_name, self.storage) {
+            (Some(name), _) => name.clone(),
+            (None, StorageBackend::File) => "jab3.data".into(),
+            (None, StorageBackend::Sqlite) => "jab3.sqlite".into(),
+        }
+    }
+
+    pub fn autosave_interval(&self) -> Option<Duration> {
+        (self.autosave_interval > 0).then(|| Duration::from_secs(self.autosave_interval))
+    }
+
+    pub fn backoff(&self) -> BackoffConfig {
+        let backoff = &self.backoff;
+        BackoffConfig {
+            initial_delay: Duration::from_secs(backoff.initial_delay),
+            max_delay: Duration::from_secs(backoff.max_delay),
+            multiplier: backoff.multiplier,
+            jitter: backoff.jitter,
+            circuit_threshold: backoff.circuit_threshold,
+            conflict_delay: Duration::from_secs(backoff.conflict_delay),
+        }
+    }
+
+    pub fn supervisor(&self) -> SupervisorConfig {
+        SupervisorConfig {
+            timeout: Duration::from_secs(self.supervisor.timeout),
+            failure_limit: self.supervisor.failure_limit,
+            reply_on_error: self.supervisor.reply_on_error,
+        }
+    }
+
+    pub fn filters(&self) -> eyre::Result<FilterConfig> {
+        let filters = &self.filters;
+        Ok(FilterConfig {
+            allowed_chats: parse_ids::<ChatIntId>(&filters.allowed_chats, "chat")?,
+            banned_users: parse_ids(&filters.banned_users, "user")?,
+            ignore_bots: filters.ignore_bots,
+            ignore_forwarded: filters.ignore_forwarded,
+            metrics_every: filters.metrics_every.filter(|every| *every > 0),
+        })
+    }
+}
+
+/// Retry policy for failed update fetching, delays in s
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BackoffSection {
+    pub initial_delay: u64,
+    pub max_delay: u64,
+    pub multiplier: f64,
+    /// Relative random deviation of each delay, 0.1 means ±10%
+    pub jitter: f64,
+    /// Consecutive failures after which retries happen at the max delay only
+    pub circuit_threshold: u32,
+    /// Min delay after 409 Conflict, gives the other instance time to shut down
+    pub conflict_delay: u64,
+}
+
+impl Default for BackoffSection {
+    fn default() -> Self {
+        let config = BackoffConfig::default();
+        Self {
+            initial_delay: config.initial_delay.as_secs(),
+            max_delay: config.max_delay.as_secs(),
+            multiplier: config.multiplier,
+            jitter: config.jitter,
+            circuit_threshold: config.circuit_threshold,
+            conflict_delay: config.conflict_delay.as_secs(),
+        }
+    }
 }
 
-/*
-impl ConnectorConfig {
-    pub fn verify(&self) -> eyre::Result<()> {
+impl BackoffSection {
+    fn verify(&self) -> eyre::Result<()> {
         ensure!(
-            matches!(self.update_limit, Some(1..=100)),
-            "number of updates to retrieve is strictly 1-100"
+            self.initial_delay <= self.max_delay,
+            "initial backoff delay exceeds the max one"
+        );
+        ensure!(self.multiplier >= 1.0, "backoff multiplier is less than 1");
+        ensure!(
+            (0.0..1.0).contains(&self.jitter),
+            "backoff jitter is out of 0-1"
         );
-        if matches!(self.timeout, Some(0)) {
-            warn!(
-                "timeout = 0, i.e. short polling; should be positive,
-                short polling should be used for testing purposes only"
-            );
-        }
         Ok(())
     }
-}*/
+}
+
+/// Timeouts and failure limits of module handlers
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SupervisorSection {
+    /// Max time a module may spend on a single update, s
+    pub timeout: u64,
+    /// Consecutive timeouts or panics after which the module is disabled,
+    /// none to never disable
+    pub failure_limit: Option<u32>,
+    /// Whether to tell the chat that its command failed
+    pub reply_on_error: bool,
+}
+
+impl Default for SupervisorSection {
+    fn default() -> Self {
+        let config = SupervisorConfig::default();
+        Self {
+            timeout: config.timeout.as_secs(),
+            failure_limit: config.failure_limit,
+            reply_on_error: config.reply_on_error,
+        }
+    }
+}
+
+/// Updates that never reach the modules, all filters are off by default
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FiltersSection {
+    /// Comma separated ids of the chats the bot works in, any if empty
+    pub allowed_chats: CompactString,
+    /// Comma separated ids of the users whose updates are dropped
+    pub banned_users: CompactString,
+    pub ignore_bots: bool,
+    pub ignore_forwarded: bool,
+    /// Logs update counts and handling time every that many updates, none or 0 to never log
+    pub metrics_every: Option<u64>,
+}
+
+fn parse_ids<T>(ids: &str, kind: &str) -> eyre::Result<HashSet<T>>
+where
+    T: FromStr + Eq + std::hash::Hash,
+    T::Err: Display,
+{
+    ids.split(',')
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| {
+            id.trim()
+                .parse()
+                .map_err(|err| eyre!("invalid {kind} id '{id}', {err}"))
+        })
+        .collect()
+}
+
+fn env_override<T>(name: &str, value: &mut T) -> eyre::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(var) = dotenv::var(name) {
+        *value = var
+            .parse()
+            .map_err(|err| eyre!("invalid {name} '{var}', {err}"))?;
+    }
+    Ok(())
+}
 
-/*#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
-pub struct AllowedUpdates {
-    #[serde(rename = "$value")]
-    pub upd_vec: Vec<UpdateTypeItem>,
+/// An empty var unsets the value
+fn env_optional<T>(name: &str, value: &mut Option<T>) -> eyre::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(var) = dotenv::var(name) {
+        *value = match var.as_str() {
+            "" => None,
+            _ => Some(
+                var.parse()
+                    .map_err(|err| eyre!("invalid {name} '{var}', {err}"))?,
+            ),
+        };
+    }
+    Ok(())
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub struct UpdateTypeItem {
-    #[serde(rename = "type")]
-    pub update_type: UpdateType,
+/// "1", "true" or "on" sets the flag, "0", "false" or "off" clears it, case-insensitive
+fn env_flag(name: &str, value: &mut bool) -> eyre::Result<()> {
+    if let Ok(var) = dotenv::var(name) {
+        *value =
+            parse_flag(&var).ok_or_else(|| eyre!("invalid {name} '{var}', expected on or off"))?;
+    }
+    Ok(())
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq, Eq)]
-pub struct BirthminderConfig {
-    pub timezone: Timezone,
-}*/
+fn parse_flag(var: &str) -> Option<bool> {
+    match var.to_lowercase().as_str() {
+        "1" | "true" | "on" => Some(true),
+        "0" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
 
-/*#[cfg(test)]
+#[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{
-        command::{
-            BotCommandScope, BotCommandScopeChatMember, BotCommandScopeDefault, Command,
-            CommandName,
-        },
-        connector::config::ConnectorConfig,
-    };
-    use api::proto::ChatId;
-    use imager::config::ImagerConfig;
-    use std::process::Command;
+    use api::proto::UpdateType;
 
     #[test]
     fn load_template_config() {
-        let connector = ConnectorConfig {
-            allowed_updates: Some(AllowedUpdates {
-                upd_vec: vec![
-                    UpdateTypeItem {
-                        update_type: UpdateType::Message,
-                    },
-                    UpdateTypeItem {
-                        update_type: UpdateType::EditedMessage,
-                    },
-                    UpdateTypeItem {
-                        update_type: UpdateType::ChannelPost,
-                    },
-                ],
-            }),
-            limit: Some(100),
-            rps: 30,
-            timeout: None,
-            update_channel_size: 1024,
-        };
-        let commands = Commands {
-            cmd_vec: vec![
-                Command {
-                    scope: BotCommandScope::Default(BotCommandScopeDefault),
-                    name: CommandName::SetDay,
-                    desc: "dd.mm.yyyy format".into(),
-                },
-                Command {
-                    scope: BotCommandScope::Default(BotCommandScopeDefault),
-                    name: CommandName::Please,
-                    desc: "ordered google image search".into(),
-                },
-                Command {
-                    scope: BotCommandScope::ChatMember(BotCommandScopeChatMember {
-                        chat_id: ChatId::from(-1001367947767),
-                        user_id: 5363186157,
-                    }),
-                    name: CommandName::Pls,
-                    desc: "randomised google image search".into(),
-                },
-            ],
-        };
-        let imager = ImagerConfig { limit: 100 };
-        let birthminder = BirthminderConfig {
-            timezone: Timezone::Utc,
-        };
-        let config = GlobalConfig {
-            connector,
-            commands,
-            imager,
-            birthminder,
-        };
+        let config = GlobalConfig::parse("bot/test/config.template.xml").unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.storage, StorageBackend::Sqlite);
+        assert_eq!(config.data_file_name(), "jab3.sqlite");
+        assert_eq!(config.autosave_interval(), None);
+        assert_eq!(config.owners().unwrap(), HashSet::from([1, 2]));
         assert_eq!(
-            config,
-            GlobalConfig::from_file("bot/test/config.template.xml").unwrap()
-        )
+            config.connector.allowed_updates,
+            HashSet::from([
+                UpdateType::Message,
+                UpdateType::EditedMessage,
+                UpdateType::ChannelPost
+            ])
+        );
+        assert_eq!(config.connector.update_limit, Some(100));
+        assert_eq!(config.connector.mode, ConnectorMode::Webhook);
+        assert_eq!(config.connector.webhook.port, 8443);
+        assert!(!config.connector.webhook.tls);
+        assert_eq!(config.backoff().initial_delay, Duration::from_secs(2));
+        assert_eq!(config.backoff().max_delay, Duration::from_secs(600));
+        assert_eq!(config.backoff().circuit_threshold, 5);
+        assert_eq!(config.supervisor().timeout, Duration::from_secs(30));
+        assert_eq!(config.supervisor().failure_limit, Some(3));
+        assert!(config.supervisor().reply_on_error);
+        let filters = config.filters().unwrap();
+        assert_eq!(filters.allowed_chats, HashSet::from([-100, 3]));
+        assert_eq!(filters.banned_users, HashSet::from([4]));
+        assert!(filters.ignore_bots);
+        assert!(!filters.ignore_forwarded);
+        assert_eq!(filters.metrics_every, Some(1000));
+        assert_eq!(config.imager.limit, 50);
+        assert_eq!(config.imager.max_reply_attempts, 5);
+        assert_eq!(config.gigachat, GigaChatConfig::default());
+    }
+
+    #[test]
+    fn flags_are_strict() {
+        for var in ["1", "true", "ON", "True"] {
+            assert_eq!(parse_flag(var), Some(true));
+        }
+        for var in ["0", "false", "Off"] {
+            assert_eq!(parse_flag(var), Some(false));
+        }
+        for var in ["", "yes", " on", "2"] {
+            assert_eq!(parse_flag(var), None);
+        }
+    }
+
+    #[test]
+    fn connector_mode_keeps_old_names() {
+        for (xml, mode) in [
+            ("<mode>webhook</mode>", ConnectorMode::Webhook),
+            ("<mode>Webhook</mode>", ConnectorMode::Webhook),
+            ("<mode>Polling</mode>", ConnectorMode::Polling),
+        ] {
+            let xml = format!("<config><connector>{xml}</connector></config>");
+            let config = serde_xml_rs::from_str::<GlobalConfig>(&xml).unwrap();
+            assert_eq!(config.connector.mode, mode);
+        }
     }
 }
-*/
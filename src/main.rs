@@ -1,65 +1,199 @@
 mod config;
 
 use crate::config::GlobalConfig;
+use api::basic_types::ChatIntId;
 use archivarius::archivarius::Archivarius;
-use bot::bot::{config::BotConfig, Bot, State};
-use gigachat::GigaChat;
+use bot::{
+    bot::{chat_settings::TriggerPolicy, config::BotConfig, Bot, State},
+    communicator::{Communicate, Communicator},
+    connector::ConnectorMode,
+};
+use clap::{Parser, Subcommand};
+use eyre::{eyre, WrapErr};
+use gigachat::{config::GigaChatConfig, GigaChat};
 use imager::imager::Imager;
-use log::LevelFilter;
+use log::{error, LevelFilter};
 use simple_logger::SimpleLogger;
 use std::path::{Path, PathBuf};
 use tokio::{signal, sync::mpsc};
 
-#[tokio::main]
-async fn main() {
-    let token = dotenv::var("TOKEN").expect("no token in env");
+#[derive(Parser)]
+#[command(version, about = "Just another Telegram bot")]
+struct Cli {
+    /// `config.xml` in the work dir by default
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// off, error, warn, info, debug or trace
+    #[arg(short, long, global = true, default_value = "debug")]
+    log_level: LevelFilter,
+    /// polling or webhook, overrides the config
+    #[arg(long, global = true)]
+    connector_mode: Option<ConnectorMode>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    SimpleLogger::new()
-        .with_level(LevelFilter::Off)
-        .with_module_level("jab3", LevelFilter::Debug)
-        .with_module_level("bot", LevelFilter::Debug)
-        .with_module_level("api", LevelFilter::Debug)
-        .with_module_level("imager", LevelFilter::Debug)
-        .with_module_level("birthminder", LevelFilter::Debug)
-        .with_module_level("archivarius", LevelFilter::Debug)
-        .with_module_level("gigachat", LevelFilter::Debug)
-        .init()
-        .expect("logger failure");
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the bot, the default
+    Run,
+    /// Loads the config and reports what is wrong with it
+    CheckConfig,
+    /// Writes the bot data as JSON
+    ExportData { output: PathBuf },
+    /// Replaces the bot data with JSON written by `export-data`,
+    /// modules missing from it keep their data
+    ImportData { input: PathBuf },
+    /// Handles the updates of a journal, a Telegram update as JSON per line,
+    /// and stops; neither the data nor the update offset is saved
+    Replay {
+        journal: PathBuf,
+        /// Sends the replies to the chats instead of only logging them
+        #[arg(long)]
+        send: bool,
+    },
+    /// Sends a message on behalf of the bot
+    Send {
+        #[arg(allow_negative_numbers = true)]
+        chat: ChatIntId,
+        #[arg(required = true)]
+        text: Vec<String>,
+    },
+}
 
-    let work_dir = dotenv::var("WORK_DIR").unwrap();
-    let path = Path::new(work_dir.as_str()).join(Path::new("config.xml"));
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let cli = Cli::parse();
+    init_logger(cli.log_level)?;
+
+    let work_dir = env_var("WORK_DIR")?;
+    let config_path = cli
+        .config
+        .unwrap_or_else(|| Path::new(&work_dir).join("config.xml"));
+    let mut config = GlobalConfig::from_file(&config_path)
+        .wrap_err_with(|| format!("failed to load config {config_path:?}"))?;
+    if let Some(connector_mode) = cli.connector_mode {
+        config.connector.mode = connector_mode;
+        config
+            .validate()
+            .wrap_err_with(|| format!("invalid config {config_path:?}"))?;
+    }
 
-    let config = GlobalConfig::from_file(path.as_path()).expect("failed to load config");
+    let command = cli.command.unwrap_or(Command::Run);
+    let token = match command {
+        Command::CheckConfig => {
+            println!("{config_path:?} is fine\n{config:#?}");
+            return Ok(());
+        }
+        // the data is handled without connecting to Telegram
+        Command::ExportData { .. } | Command::ImportData { .. } => {
+            dotenv::var("TOKEN").unwrap_or_default()
+        }
+        Command::Run | Command::Replay { .. } | Command::Send { .. } => env_var("TOKEN")?,
+    };
+    if let Command::Send { chat, text } = &command {
+        Communicator::new(&token)
+            .send_message(&text.join(" "), (*chat).into())
+            .await?
+            .into_result()?;
+        return Ok(());
+    }
 
     let (tx, rx) = mpsc::channel::<State>(1);
 
+    let work_dir = PathBuf::from(work_dir);
     let bot_config = BotConfig {
-        skip_missed_updates: false,
-        connector_mode: config.connector_mode,
-        allowed_updates: Default::default(),
-        update_limit: None,
-        polling_timeout: None,
-        work_dir: PathBuf::from(work_dir),
-        data_file_name: config.data_file_name,
-        ..Default::default()
+        connector: config.connector.clone(),
+        journal: match &command {
+            Command::Replay { journal, .. } => Some(journal.clone()),
+            _ => None,
+        },
+        dry_run: matches!(command, Command::Replay { send: false, .. }),
+        read_only: matches!(command, Command::ExportData { .. }),
+        work_dir: work_dir.clone(),
+        data_file_name: config.data_file_name(),
+        autosave_interval: config.autosave_interval(),
+        backups: config.backups,
+        storage: config.storage,
+        triggers: TriggerPolicy {
+            bare_words: config.bare_word_commands,
+            ..Default::default()
+        },
+        backoff: config.backoff(),
+        supervisor: config.supervisor(),
+        filters: config.filters()?,
+        owners: config.owners()?,
     };
-    let mut bot = Bot::with_config(token.as_str(), rx, bot_config);
+    let mut bot = Bot::with_config(token.as_str(), rx, bot_config)?;
 
-    bot.add_module("imager", Imager::new());
+    let gigachat_config = GigaChatConfig {
+        cert_path: work_dir.join(&config.gigachat.cert_path),
+        ..config.gigachat
+    };
+    bot.add_module("imager", Imager::new_with_config(config.imager));
     bot.add_module("archivarius", Archivarius::new());
-    bot.add_module("gigachat", GigaChat::new());
+    bot.add_module("gigachat", GigaChat::with_config(gigachat_config)?);
     // bot.add_module("birthminder", Birthminder::new());
 
-    tokio::join!(bot.start(), async {
-        match signal::ctrl_c().await {
-            Ok(()) => {
-                tx.send(State::Shutdown)
-                    .await
-                    .expect("failed to send shutdown signal");
-            }
-            Err(err) => {
-                panic!("unable to listen for shutdown signal: {}", err);
-            }
-        };
-    });
+    match command {
+        Command::ExportData { output } => {
+            let data = bot.export_data().wrap_err("failed to export data")?;
+            let json = serde_json::to_string_pretty(&data)?;
+            std::fs::write(&output, json)
+                .wrap_err_with(|| format!("failed to write {output:?}"))?;
+        }
+        Command::ImportData { input } => {
+            let json = std::fs::read_to_string(&input)
+                .wrap_err_with(|| format!("failed to read {input:?}"))?;
+            let data = serde_json::from_str(&json)
+                .wrap_err_with(|| format!("failed to parse {input:?}"))?;
+            bot.import_data(data).wrap_err("failed to import data")?;
+        }
+        _ => {
+            // the bot may stop by itself, e.g. once the journal is replayed
+            tokio::spawn(async move {
+                match shutdown_signal().await {
+                    Ok(()) => {
+                        let _ = tx.send(State::Shutdown).await;
+                    }
+                    Err(err) => error!("unable to listen for shutdown signal, {err}"),
+                }
+            });
+            bot.start().await;
+        }
+    }
+    Ok(())
+}
+
+fn init_logger(level: LevelFilter) -> eyre::Result<()> {
+    SimpleLogger::new()
+        .with_level(LevelFilter::Off)
+        .with_module_level("jab3", level)
+        .with_module_level("bot", level)
+        .with_module_level("api", level)
+        .with_module_level("imager", level)
+        .with_module_level("birthminder", level)
+        .with_module_level("archivarius", level)
+        .with_module_level("gigachat", level)
+        .init()?;
+    Ok(())
+}
+
+/// Taken from `.env` as well
+fn env_var(name: &str) -> eyre::Result<String> {
+    dotenv::var(name).map_err(|_| eyre!("{name} is not set, neither in env nor in .env"))
+}
+
+/// Ctrl-C or SIGTERM, the latter is what `docker stop` sends
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
 }